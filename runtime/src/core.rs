@@ -1,59 +1,186 @@
 use std::{
     cmp::Ordering,
-    collections::{BTreeMap, HashMap},
-    fmt::Debug,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{Debug, Write},
+    sync::Arc,
 };
 
 use anyhow::Result;
 use error::RuntimeError;
-use glam::Mat4;
-use swf::{CharacterId, Twips};
+use glam::{Mat4, Vec2};
+use swf::{CharacterId, Depth, Rectangle, Twips};
+use tracing::warn;
 
 use crate::parser::{
-    Animation, DepthTimeline, KeyFrame, MovieClip, Transform, parse_shape::matrix::Matrix,
-    types::BlendMode,
+    Animation, DepthTimeline, Event, KeyFrame, MovieClip, Placement, Transform,
+    parse_shape::matrix::Matrix, types::BlendMode,
 };
 
+pub use baked::{BakedAnimation, BakedAnimations, BakedPlayer};
+use bounds::instance_bounds;
+use clip::ClipControl;
+pub use clip::{ClipController, FrameTarget};
+use diff::DiffTracker;
+pub use diff::InstanceDiff;
+use display::DisplaySettings;
+use event::{
+    EventSink, FRAME_EPSILON, FrameEventListener, Marker, TimeSegment, dispatch_timeline_events,
+    notify_listeners,
+};
+pub use event::{ListenerId, PlayerEvent};
 use filter::Filter as RenderFilter;
+use frame_clock::FrameClock;
+pub use hit_test::InstanceHit;
+use hit_test::instance_contains;
+pub use layer_tree::{LayerGroup, LayerNode, LayerTree};
+pub use library::AnimationLibrary;
+use overrides::{InstanceOverride, find_override};
+pub use pool::{PlayerId, PlayerPool};
+use root_motion::RootMotionState;
+pub use root_motion::{RootMotion, RootMotionDelta, RootMotionSource};
+pub use sample::{SampleOptions, sample};
+use serde::{Deserialize, Serialize};
+pub use skin::SkinCatalog;
+use snapshot::ClipState;
+pub use snapshot::PlayerState;
+pub use state_machine::{
+    Condition, ParameterDefinition, ParameterValue, StateDefinition, StateMachine,
+    StateMachineDefinition, TransitionDefinition,
+};
 
+mod baked;
+mod bounds;
+mod clip;
+mod diff;
+mod display;
 mod error;
+mod event;
 pub mod filter;
+mod frame_clock;
+mod hit_test;
+mod layer_tree;
+mod library;
+mod overrides;
+mod pool;
+pub mod render_list;
+mod root_motion;
+mod sample;
+mod skin;
+mod snapshot;
 mod state_machine;
 
 type CompletionCallback = Box<dyn FnOnce() + Send + Sync + 'static>;
-type FrameEventCallback = Box<dyn Fn() + Send + Sync + 'static>;
+
+/// 循环模式
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoopMode {
+    /// 只播放一次
+    #[default]
+    Once,
+    /// 无限循环
+    Loop,
+    /// 往返播放，到达一端后反向播放
+    PingPong,
+    /// 播放指定次数后结束，`Count(0)`与`Count(1)`等同于`Once`
+    Count(u32),
+}
+
+impl From<bool> for LoopMode {
+    fn from(looping: bool) -> Self {
+        if looping {
+            LoopMode::Loop
+        } else {
+            LoopMode::Once
+        }
+    }
+}
+
+/// 一次时间推进的结果
+#[derive(Debug, Default)]
+struct TimeAdvance {
+    /// 播放头经过的时间区间，按经过顺序排列
+    segments: Vec<TimeSegment>,
+    /// 跨过首尾边界的次数，包括跳过的轮次
+    boundaries: u32,
+    /// 一次推进跨过超过[`MAX_FULL_LOOPS`]轮时跳过的完整轮次，跳过的轮次位于第一段之后，不产生区间
+    skipped_loops: u32,
+    /// 是否从头（倒放时从尾）开始了新一轮循环
+    wrapped: bool,
+    /// 是否播放完成
+    finished: bool,
+}
 
 #[derive(Default)]
 pub struct AnimationPlayer {
     // ---------资源-----------
-    /// 原flash动画帧率
-    frame_rate: f32,
-    /// 动画数据
-    animations: HashMap<String, Animation>,
-    /// 动画子影片资源
-    children_clip: HashMap<CharacterId, MovieClip>,
-    /// 活动的子影片存在同一个被多次使用的情况
-    active_clip: HashMap<String, MovieClip>,
+    /// 共享的动画资源
+    library: Arc<AnimationLibrary>,
+    /// 活动的子影片存在同一个被多次使用的情况，放置时创建，移除后丢弃
+    active_clip: HashMap<u64, ActiveClip>,
     /// 运行时实例，扁平化结构
     active_instances: Vec<RuntimeInstance>,
 
     // ----------控制-----------
-    /// 播放速度
+    /// 播放速度，负数为倒放
     speed: f32,
-    /// 是否循环
-    looping: bool,
+    /// 循环模式
+    loop_mode: LoopMode,
+    /// 已经完成的循环次数
+    completed_loops: u32,
+    /// 往返播放时是否处于反向阶段
+    reversed: bool,
     /// 当前时间
     current_time: f32,
+    /// 最近一次输出的时间，跳转或切换动画后还没有输出时为`None`
+    output_time: Option<f32>,
     /// 是否播放
     playing: bool,
+    /// 跳转后即使处于暂停状态也需要重新输出一次实例
+    needs_redraw: bool,
+    /// 跳转时是否触发被跳过的帧事件
+    seek_fires_events: bool,
     /// 当前动画名
     current_animation_name: Option<String>,
     /// 皮肤
     current_skins: HashMap<String, String>,
     /// 播放完成时回调
     on_completion: Option<CompletionCallback>,
-    /// 用于帧事件
-    frame_event_listeners: HashMap<String, Vec<FrameEventCallback>>,
+    /// 用于帧事件，Key为事件名
+    frame_event_listeners: HashMap<String, Vec<FrameEventListener>>,
+    /// 下一个监听器句柄
+    next_listener_id: u64,
+    /// 是否收集事件到队列中
+    event_queue_enabled: bool,
+    /// 等待拉取的事件
+    event_queue: Vec<PlayerEvent>,
+    /// 子影片实例的播放控制，Key为实例名或实例路径
+    clip_controls: HashMap<String, ClipControl>,
+    /// 开启后记录每次输出相对上一次输出的差异
+    instance_diff: Option<DiffTracker>,
+    /// 实例的运行时覆盖，Key为实例名或实例路径
+    overrides: HashMap<String, InstanceOverride>,
+    /// 最近一次输出的世界包围盒
+    bounds: Rectangle<Twips>,
+    /// 最近一次输出包含滤镜扩展的世界包围盒
+    filter_bounds: Rectangle<Twips>,
+    /// 根运动提取
+    root_motion: Option<RootMotionState>,
+    /// 逐帧播放，为`None`时按连续时间播放
+    frame_clock: Option<FrameClock>,
+    /// 逐帧播放时是否在帧之间插值，关闭逐帧播放后仍然保留
+    frame_interpolation: bool,
+    /// 作用于整个播放器的变换、翻转和颜色
+    display: DisplaySettings,
+    // ----------复用的缓冲区-----------
+    /// 遍历时间轴时使用
+    collect_buffers: CollectBuffers,
+    /// 一次更新中经过的时间区间
+    time_advance: TimeAdvance,
+    /// 一段时间区间内经过的时间轴标记
+    timeline_markers: Vec<(f32, Marker)>,
+    /// 本次更新入队的事件在推进中的位置
+    event_progress: Vec<f32>,
 }
 
 impl AnimationPlayer {
@@ -63,370 +190,2142 @@ impl AnimationPlayer {
         children_clip: HashMap<CharacterId, MovieClip>,
         frame_rate: f32,
     ) -> Self {
-        Self {
+        Self::with_library(Arc::new(AnimationLibrary::new(
             animations,
             children_clip,
-            speed: 1.0,
             frame_rate,
+        )))
+    }
+
+    /// 使用共享的动画资源创建播放器，同一份资源可以被任意数量的播放器使用
+    pub fn with_library(library: Arc<AnimationLibrary>) -> Self {
+        Self {
+            library,
+            speed: 1.0,
             playing: true,
             ..Default::default()
         }
     }
 
+    pub fn library(&self) -> &Arc<AnimationLibrary> {
+        &self.library
+    }
+
     pub fn update(&mut self, active_instances: &mut Vec<RuntimeInstance>, delta_time: f32) {
-        if !self.playing || self.current_animation_name.is_none() {
+        if self.current_animation_name.is_none() {
+            return;
+        }
+        if !self.playing {
+            // 暂停状态下跳转过，需要输出跳转后的画面
+            if self.needs_redraw {
+                self.redraw(active_instances);
+            }
             return;
         }
+        self.needs_redraw = false;
 
-        let animation_name = self.current_animation_name.as_ref().unwrap().clone();
+        // 持有资源的引用计数，借用动画时不占用播放器
+        let library = Arc::clone(&self.library);
+        let animation = self
+            .current_animation_name
+            .as_deref()
+            .and_then(|name| library.animation(name))
+            .unwrap();
 
         // 1. Time Advancement & Looping
         let previous_time = self.current_time;
-        let elapsed_time = delta_time * self.speed;
-        self.current_time += elapsed_time;
-
-        let animation = self.animations.get(&animation_name).unwrap();
+        let direction = if self.reversed { -1.0 } else { 1.0 };
+        let elapsed_time = match self.frame_clock.as_mut() {
+            Some(clock) => {
+                let frame_rate = library.frame_rate();
+                let frames = clock.step(delta_time * self.speed, frame_rate);
+                frames as f32 / frame_rate * (delta_time * self.speed).signum() * direction
+            }
+            None => delta_time * self.speed * direction,
+        };
         let duration = animation.duration;
-        let mut on_completion = None;
-
-        if self.current_time >= duration {
-            if self.looping {
-                self.current_time %= duration;
-                // 子动画也需要重置
-                self.active_clip.iter_mut().for_each(|(_, v)| {
-                    v.current_time = self.current_time;
-                });
+        self.advance_time(elapsed_time, duration);
+        let advance = std::mem::take(&mut self.time_advance);
+        if self.frame_clock.is_some() {
+            // 消除浮点累加误差，播放头始终落在帧上
+            self.current_time = self.snap_to_frame(self.current_time).min(duration);
+        }
+
+        // 2.Instance Lifecycle & Property Updates (Iterate through Depths)
+        // 子影片的事件在遍历时入队，随后与根时间轴的事件按经过的先后合并
+        let first_event = self.event_queue.len();
+        self.event_progress.clear();
+        self.collect_instances(active_instances, previous_time, elapsed_time);
+        // 这次跨过的循环边界在下一次输出时计入根运动
+        if let Some(root_motion) = self.root_motion.as_mut() {
+            let wraps = if self.loop_mode == LoopMode::PingPong {
+                0
             } else {
-                self.current_time = duration;
-                self.playing = false;
+                advance.boundaries - advance.finished as u32
+            };
+            root_motion.record_wraps(wraps, elapsed_time >= 0.0);
+        }
 
-                // TODO: 触发动画完成事件
-                on_completion = self.on_completion.take();
+        // 3.Frame Event Handle
+        let mut sink = self.event_queue_enabled.then_some(EventSink {
+            queue: &mut self.event_queue,
+            progress: &mut self.event_progress,
+        });
+        let loops_before = self.completed_loops - advance.boundaries;
+        let last_segment = advance.segments.len() - 1;
+        let mut progress = 0.0;
+        for (index, segment) in advance.segments.iter().enumerate() {
+            dispatch_timeline_events(
+                animation,
+                library.frame_rate(),
+                *segment,
+                &self.frame_event_listeners,
+                sink.as_mut(),
+                &mut self.timeline_markers,
+                progress,
+            );
+            progress += (segment.to - segment.from).abs();
+            // 除了最后一段，每段都结束于一次首尾边界，跳过的轮次在第一段之后
+            if index < last_segment
+                && let Some(sink) = sink.as_mut()
+            {
+                let skipped = if index > 0 { advance.skipped_loops } else { 0 };
+                sink.push(
+                    PlayerEvent::Looped {
+                        animation: animation.name.clone(),
+                        loop_count: loops_before + index as u32 + 1 + skipped,
+                    },
+                    progress,
+                );
             }
         }
+        if let Some(sink) = sink.as_mut() {
+            sink.sort_from(first_event);
+        }
+        let wrapped = advance.wrapped;
+        let finished = advance.finished;
+        // 放回去以复用区间列表的空间
+        self.time_advance = advance;
 
-        let children_clip = &self.children_clip;
-        let active_clip = &mut self.active_clip;
-        let current_skins = &mut self.current_skins;
+        // 新一轮循环，子动画也需要重置
+        if wrapped {
+            self.rebuild_active_clip();
+        }
 
-        // 2.Instance Lifecycle & Property Updates (Iterate through Depths)
-        active_instances.clear();
-        let base_transform = Matrix::IDENTITY;
-        let base_color_transform = swf::ColorTransform::IDENTITY;
-        // 实例标识，用于防止重复生成
-        let id = "root";
-        collect_current_time_active_shape(
-            id,
-            &animation.timeline,
-            previous_time,
+        // 触发完成事件
+        if finished {
+            if self.event_queue_enabled {
+                self.event_queue.push(PlayerEvent::Completed {
+                    animation: animation.name.clone(),
+                });
+            }
+            if let Some(on_completion) = self.on_completion.take() {
+                on_completion();
+            }
+        }
+    }
+
+    /// 收集`time`时刻的活动实例，子影片随后推进`elapsed_time`
+    fn collect_instances(
+        &mut self,
+        active_instances: &mut Vec<RuntimeInstance>,
+        time: f32,
+        elapsed_time: f32,
+    ) {
+        self.output_time = Some(time);
+        let Some(animation) = self
+            .current_animation_name
+            .as_ref()
+            .and_then(|name| self.library.animation(name))
+        else {
+            return;
+        };
+        let root = Inherited {
+            transform: self.display.transform(),
+            color_transform: self.display.color_transform(),
+            ..Inherited::default()
+        };
+        let mut collector = Collector {
+            children_clip: self.library.children_clip(),
+            active_clip: &mut self.active_clip,
+            clip_controls: &mut self.clip_controls,
+            overrides: &self.overrides,
+            current_skins: &self.current_skins,
+            frame_rate: self.library.frame_rate(),
             elapsed_time,
-            active_clip,
-            children_clip,
-            current_skins,
-            self.frame_rate,
             active_instances,
-            base_transform,
-            base_color_transform,
-            BlendMode::Normal,
-            Vec::new(),
-        )
-        .unwrap();
+            instance_count: 0,
+            buffers: &mut self.collect_buffers,
+            events: self.event_queue_enabled.then_some(EventSink {
+                queue: &mut self.event_queue,
+                progress: &mut self.event_progress,
+            }),
+            listeners: &self.frame_event_listeners,
+            animation_name: &animation.name,
+            root_motion_source: self.root_motion.as_ref().map(|state| &state.config.source),
+            root_motion_reference: None,
+            interpolation: self
+                .frame_clock
+                .as_ref()
+                .and_then(|clock| clock.interpolation),
+        };
+        // 实例标识，用于防止重复生成
+        collector
+            .collect(ROOT_INSTANCE_ID, "", &animation.timeline, time, root)
+            .unwrap();
+        // 跳转已经作用于这次遍历到的所有匹配实例
+        for control in collector.clip_controls.values_mut() {
+            if std::mem::take(&mut control.goto_applied) {
+                control.goto = None;
+            }
+        }
+        let root_motion_reference = collector.root_motion_reference;
+        let instance_count = collector.instance_count;
+        active_instances.truncate(instance_count);
+        // 已经从显示列表中移除的子影片不再保留，下次放置时从头播放
+        self.active_clip
+            .retain(|_, active| std::mem::take(&mut active.alive));
+        if let Some(reference) = root_motion_reference {
+            self.strip_root_motion(active_instances, reference);
+        }
+        if let Some(tracker) = self.instance_diff.as_mut() {
+            tracker.track(active_instances);
+        }
+        self.update_bounds(active_instances);
+        self.active_instances.clone_from(active_instances);
+    }
 
-        // 3.Frame Event Handle
-        // 处理时间值精度问题
-        let cmp_current_time = (self.current_time * 1.0e6).trunc();
-        let cmp_previous_time = (previous_time * 1.0e6).trunc();
-
-        for event_keyframe in animation.events.iter() {
-            let time = (event_keyframe.time * 1.0e6).trunc();
-            if (time >= cmp_previous_time) && time < cmp_current_time {
-                if let Some(frame_events) = self.frame_event_listeners.get(&event_keyframe.name) {
-                    frame_events.iter().for_each(|event| event());
-                }
+    /// 累加根运动，并从输出的实例中剥离
+    fn strip_root_motion(&mut self, active_instances: &mut [RuntimeInstance], reference: Matrix) {
+        let needs_ends = self
+            .root_motion
+            .as_ref()
+            .is_some_and(RootMotionState::needs_ends);
+        if needs_ends {
+            let duration = self.current_animation().map_or(0.0, |a| a.duration);
+            let start = self.root_motion_reference_at(0.0).unwrap_or(reference);
+            let end = self.root_motion_reference_at(duration).unwrap_or(reference);
+            if let Some(root_motion) = self.root_motion.as_mut() {
+                root_motion.set_ends(start.into(), end.into());
             }
         }
+        let Some(root_motion) = self.root_motion.as_mut() else {
+            return;
+        };
+        let strip = root_motion.advance(reference.into());
+        for instance in active_instances {
+            instance.transform = strip * instance.transform;
+        }
+    }
 
-        // 触发完成事件
-        if let Some(on_completion) = on_completion {
-            on_completion();
+    /// 根运动参照在指定时间的世界变换
+    fn root_motion_reference_at(&self, time: f32) -> Option<Matrix> {
+        let animation = self.current_animation()?;
+        match &self.root_motion.as_ref()?.config.source {
+            RootMotionSource::Root => animation.timeline.values().find_map(|depth_timeline| {
+                let (Some(start), _) = find_key_frame(time, &depth_timeline.placement) else {
+                    return None;
+                };
+                depth_timeline.placement[start].resource_id()?;
+                let (Some(transform), _) = find_key_frame(time, &depth_timeline.transforms) else {
+                    return None;
+                };
+                Some(self.display.transform() * depth_timeline.transforms[transform].matrix)
+            }),
+            RootMotionSource::Instance(name) => self.find_instance_transform(
+                &mut TransformSearch::new(name),
+                ROOT_INSTANCE_ID,
+                "",
+                &animation.timeline,
+                time,
+                self.display.transform(),
+            ),
         }
     }
 
-    pub fn active_instances(&self) -> &Vec<RuntimeInstance> {
-        &self.active_instances
+    /// 设置整个动画的世界变换，作用于所有输出的实例，包围盒、点击测试和挂点查询都会考虑它
+    pub fn set_root_transform(&mut self, transform: Matrix) {
+        self.display.root_transform = transform;
+        self.display_changed();
     }
 
-    pub fn animation_names(&self) -> Vec<&String> {
-        self.animations.keys().collect::<Vec<_>>()
+    pub fn root_transform(&self) -> Matrix {
+        self.display.root_transform
     }
 
-    /// 设置播放动画
-    /// - name 动画名
-    /// - looping 是否循环播放
-    /// - on_completion 回调事件
-    pub fn set_play_animation(
-        &mut self,
-        name: &str,
-        looping: bool,
-        on_completion: Option<CompletionCallback>,
-    ) -> Result<()> {
-        if !self.animations.contains_key(name) {
-            return Err(RuntimeError::AnimationNotFound(name.to_owned()).into());
+    /// 以动画的注册点为中心水平翻转，常用于切换朝向
+    pub fn set_flip_x(&mut self, flip: bool) {
+        self.display.flip_x = flip;
+        self.display_changed();
+    }
+
+    pub fn flip_x(&self) -> bool {
+        self.display.flip_x
+    }
+
+    /// 以动画的注册点为中心垂直翻转
+    pub fn set_flip_y(&mut self, flip: bool) {
+        self.display.flip_y = flip;
+        self.display_changed();
+    }
+
+    pub fn flip_y(&self) -> bool {
+        self.display.flip_y
+    }
+
+    /// 设置叠加在所有实例上的颜色变换，如受击闪白、染色
+    pub fn set_global_color_transform(&mut self, color_transform: swf::ColorTransform) {
+        self.display.color_transform = color_transform;
+        self.display_changed();
+    }
+
+    pub fn global_color_transform(&self) -> swf::ColorTransform {
+        self.display.color_transform
+    }
+
+    /// 设置整体不透明度，范围为`[0, 1]`，用于淡入淡出
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.display.opacity = if opacity.is_nan() {
+            1.0
+        } else {
+            opacity.clamp(0.0, 1.0)
+        };
+        self.display_changed();
+    }
+
+    pub fn opacity(&self) -> f32 {
+        self.display.opacity
+    }
+
+    /// 显示设置变化后，暂停状态下也需要重新输出，根运动以新的世界变换重新开始计算
+    fn display_changed(&mut self) {
+        self.needs_redraw = true;
+        if let Some(root_motion) = self.root_motion.as_mut() {
+            root_motion.reset();
         }
+    }
 
-        // 重置时间
-        self.current_time = 0.0;
-        // 清除活动实例
-        self.active_instances.clear();
+    /// 开启或关闭根运动提取。
+    ///
+    /// 开启后参照实例的位移（和旋转）会从输出的实例中剥离，参照实例保持在动画第一帧的位置，
+    /// 位移通过[`Self::take_root_motion`]获取，由游戏逻辑移动实体。循环回绕时会补上首尾之间的位移。
+    pub fn set_root_motion(&mut self, root_motion: Option<RootMotion>) {
+        self.root_motion = root_motion.map(RootMotionState::new);
+    }
 
-        self.current_animation_name = Some(name.to_owned());
-        self.looping = looping;
-        self.on_completion = on_completion;
-        Ok(())
+    pub fn root_motion(&self) -> Option<&RootMotion> {
+        self.root_motion.as_ref().map(|state| &state.config)
     }
 
-    /// 设置皮肤
-    /// - part_name 部位名
-    /// - skin_name 皮肤名
-    pub fn set_skin(&mut self, part_name: &str, skin_name: &str) -> Result<()> {
-        let skin_name = skin_name.to_owned();
-        if let Some(target_skip) = self
-            .get_skips()
-            .iter()
-            .find(|part_skips| part_skips.contains_key(part_name))
-        {
-            if target_skip.get(part_name).unwrap().contains(&&skin_name) {
-                self.current_skins.insert(part_name.to_owned(), skin_name);
-            } else {
-                return Err(RuntimeError::SkinNotFound(skin_name).into());
+    /// 取出上次调用以来累积的根运动，没有开启根运动时为零
+    pub fn take_root_motion(&mut self) -> RootMotionDelta {
+        self.root_motion
+            .as_mut()
+            .map(RootMotionState::take)
+            .unwrap_or_default()
+    }
+
+    fn update_bounds(&mut self, active_instances: &[RuntimeInstance]) {
+        let shape_bounds = self.library.shape_bounds();
+        self.bounds = Rectangle::INVALID;
+        self.filter_bounds = Rectangle::INVALID;
+        if shape_bounds.is_empty() {
+            return;
+        }
+        for instance in active_instances {
+            if let Some(bounds) = instance_bounds(instance, shape_bounds, false) {
+                self.bounds = self.bounds.clone().union(&bounds);
+            }
+            if let Some(bounds) = instance_bounds(instance, shape_bounds, true) {
+                self.filter_bounds = self.filter_bounds.clone().union(&bounds);
             }
-        } else {
-            return Err(RuntimeError::SkinPartNotFound(part_name.to_owned()).into());
         }
-        Ok(())
     }
 
-    pub fn get_skips(&self) -> Vec<HashMap<&str, Vec<&String>>> {
-        self.children_clip
-            .values()
-            .filter(|clip| clip.is_skin_frame())
-            .map(|clip| {
-                let mut skip = HashMap::new();
-                skip.insert(
-                    clip.name().expect("没有就是有Bug"),
-                    clip.skin_frames().keys().collect::<Vec<_>>(),
-                );
-                skip
+    /// 对最近一次输出做点击测试，`point`为世界坐标（像素）。
+    ///
+    /// 按绘制顺序从上到下测试图形的三角形网格，被遮罩的实例只有同时落在遮罩内才算命中，遮罩本身不会被命中。
+    /// 需要先通过[`AnimationLibrary::set_shape_meshes`]提供图形网格
+    pub fn hit_test(&self, point: Vec2) -> Option<InstanceHit> {
+        let contains = |instance: &RuntimeInstance, as_mask: bool| {
+            self.library
+                .hit_mesh(instance.id)
+                .is_some_and(|hit_mesh| instance_contains(instance, hit_mesh, point, as_mask))
+        };
+        let in_mask_layer = |layer: u64| {
+            self.active_instances
+                .iter()
+                .filter(|instance| instance.mask_layer == Some(layer))
+                .any(|instance| contains(instance, true))
+        };
+        self.active_instances
+            .iter()
+            .rev()
+            .filter(|instance| !instance.is_mask())
+            .find(|instance| {
+                contains(instance, false)
+                    && instance.masked_by.iter().all(|layer| in_mask_layer(*layer))
+            })
+            .map(|instance| InstanceHit {
+                instance_id: instance.instance_id,
+                character_id: instance.id,
+                instance_path: instance.instance_path(),
+                draw_order: instance.draw_order,
             })
-            .collect::<Vec<_>>()
     }
 
-    pub fn current_skins(&self) -> &HashMap<String, String> {
-        &self.current_skins
+    /// 最近一次输出的树形视图，带有滤镜或非Normal混合模式的子影片成为离屏分组，见[`LayerTree`]
+    pub fn layer_tree(&self) -> LayerTree {
+        LayerTree::build(&self.active_instances)
+    }
+
+    /// 最近一次输出的所有实例在世界坐标中的包围盒，
+    /// 需要先通过[`AnimationLibrary::set_shape_bounds`]提供图形的包围盒
+    pub fn bounds(&self) -> Option<Rectangle<Twips>> {
+        self.bounds.is_valid().then(|| self.bounds.clone())
+    }
+
+    /// 同[`Self::bounds`]，包含滤镜（模糊、发光、投影等）的扩展。
+    /// 滤镜按实例的世界包围盒近似扩展，不考虑缩放对滤镜尺寸的影响
+    pub fn bounds_with_filters(&self) -> Option<Rectangle<Twips>> {
+        self.filter_bounds
+            .is_valid()
+            .then(|| self.filter_bounds.clone())
     }
 
-    /// 注册一个监听特定名称帧事件的回调函数。
+    /// 推进播放时间，处理循环模式。
     ///
-    /// # Arguments
-    /// * `animation_name` - 要监听的动画名。
-    /// * `event_name` - 要监听的事件名称 (例如 "footstep", "hit_impact")。
-    /// * `callback` - 当事件触发时要调用的函数。
-    pub fn register_frame_event<T>(
-        &mut self,
-        animation_name: &str,
-        event_name: String,
-        callback: T,
-    ) -> Result<()>
-    where
-        T: Fn() + Send + Sync + 'static,
-    {
-        // 判断监听的事件是否存在
-        if let Some(animation) = self.animations.get(animation_name) {
-            if animation
-                .events
-                .iter()
-                .any(|event| event.name == event_name)
-            {
-                self.frame_event_listeners
-                    .entry(event_name)
-                    .or_default()
-                    .push(Box::new(callback));
-            } else {
-                return Err(RuntimeError::AnimationEventNotFound(event_name).into());
-            }
-        } else {
-            return Err(RuntimeError::AnimationNotFound(animation_name.to_owned()).into());
+    /// 一次推进可能跨过多次首尾边界，每段经过的区间都会按顺序记录下来用于触发事件。
+    /// 结果记录在`time_advance`中。
+    fn advance_time(&mut self, elapsed_time: f32, duration: f32) {
+        advance_clock(
+            &mut self.time_advance,
+            &mut self.current_time,
+            self.loop_mode,
+            &mut self.completed_loops,
+            &mut self.reversed,
+            elapsed_time,
+            duration,
+        );
+        if self.time_advance.finished {
+            self.playing = false;
+        }
+    }
+
+    /// 以暂停状态输出当前时间的画面，不推进时间
+    fn redraw(&mut self, active_instances: &mut Vec<RuntimeInstance>) {
+        self.needs_redraw = false;
+        self.collect_instances(active_instances, self.current_time, 0.0);
+    }
+
+    fn current_animation(&self) -> Option<&Animation> {
+        self.current_animation_name
+            .as_ref()
+            .and_then(|name| self.library.animation(name))
+    }
+
+    /// 当前时间所在的帧，从0开始计数
+    pub fn current_frame(&self) -> u32 {
+        time_to_frame(self.current_time, self.library.frame_rate())
+    }
+
+    /// 开启或关闭逐帧播放。
+    ///
+    /// 开启后每次更新累积经过的时间，只按整帧推进播放头，与Flash的ShowFrame一致，
+    /// 播放头始终落在帧上，帧事件的触发不受浮点误差影响。帧号通过[`Self::current_frame`]获取。
+    pub fn set_frame_exact(&mut self, enabled: bool) {
+        if enabled == self.frame_clock.is_some() {
+            return;
+        }
+        self.frame_clock = enabled.then(|| FrameClock::new(self.frame_interpolation));
+        if enabled {
+            self.current_time = self.snap_to_frame(self.current_time);
+        }
+    }
+
+    pub fn is_frame_exact(&self) -> bool {
+        self.frame_clock.is_some()
+    }
+
+    /// 逐帧播放时是否在帧之间插值变换，使低帧率动画在高刷新率下更平滑。
+    ///
+    /// 只对逐帧播放有效，可以在开启逐帧播放之前设置。
+    pub fn set_frame_interpolation(&mut self, interpolate: bool) {
+        self.frame_interpolation = interpolate;
+        if let Some(clock) = self.frame_clock.as_mut() {
+            clock.interpolate = interpolate;
+            clock.interpolation = None;
+        }
+    }
+
+    /// 逐帧播放时到下一帧的进度，范围为`[0, 1)`，按连续时间播放时为0
+    pub fn frame_progress(&self) -> f32 {
+        self.frame_clock
+            .as_ref()
+            .map_or(0.0, |clock| clock.progress(self.library.frame_rate()))
+    }
+
+    fn snap_to_frame(&self, time: f32) -> f32 {
+        let frame_rate = self.library.frame_rate();
+        (time * frame_rate).round() / frame_rate
+    }
+
+    /// 当前动画的总帧数
+    pub fn total_frames(&self) -> u32 {
+        self.current_animation()
+            .map(|animation| time_to_frame(animation.duration, self.library.frame_rate()))
+            .unwrap_or_default()
+    }
+
+    pub fn current_time(&self) -> f32 {
+        self.current_time
+    }
+
+    /// 跳转到指定时间（秒），超出范围会被限制在动画时长内。
+    ///
+    /// 子影片的播放进度会按跳转后的时间重新计算，与从头播放到该时间的结果一致。
+    /// 默认不会触发被跳过的帧事件，见[`Self::set_seek_fires_events`]。
+    pub fn seek(&mut self, time: f32) {
+        let Some(animation) = self.current_animation() else {
+            return;
+        };
+        let time = time.clamp(0.0, animation.duration);
+        let previous_time = self.current_time;
+
+        self.current_time = time;
+        self.output_time = None;
+        self.rebuild_active_clip();
+        self.needs_redraw = true;
+        if let Some(root_motion) = self.root_motion.as_mut() {
+            root_motion.rebase();
+        }
+        if let Some(clock) = self.frame_clock.as_mut() {
+            clock.reset();
         }
 
+        if self.seek_fires_events {
+            let animation = self
+                .current_animation_name
+                .as_ref()
+                .and_then(|name| self.library.animation(name))
+                .unwrap();
+            let mut sink = self.event_queue_enabled.then_some(EventSink {
+                queue: &mut self.event_queue,
+                progress: &mut self.event_progress,
+            });
+            dispatch_timeline_events(
+                animation,
+                self.library.frame_rate(),
+                TimeSegment::new(previous_time.min(time), previous_time.max(time)),
+                &self.frame_event_listeners,
+                sink.as_mut(),
+                &mut self.timeline_markers,
+                0.0,
+            );
+        }
+    }
+
+    /// 跳转到指定帧，帧号从0开始
+    pub fn goto_frame(&mut self, frame: u32) -> Result<()> {
+        let total_frames = self.total_frames();
+        if frame >= total_frames {
+            return Err(RuntimeError::FrameOutOfRange(frame, total_frames).into());
+        }
+        self.seek(frame as f32 / self.library.frame_rate());
         Ok(())
     }
 
-    /// 移除指定事件名称的所有监听器。
-    pub fn clear_frame_event_listeners(&mut self, event_name: &str) {
-        self.frame_event_listeners.remove(event_name);
+    /// 跳转到当前动画中的指定标签，普通标签和事件标签（不带`event_`前缀）都可以
+    pub fn goto_label(&mut self, label: &str) -> Result<()> {
+        let Some(animation) = self.current_animation() else {
+            return Err(RuntimeError::LabelNotFound(label.to_owned()).into());
+        };
+        let time = animation
+            .labels
+            .iter()
+            .find(|l| l.name == label)
+            .map(|l| l.time)
+            .or_else(|| {
+                animation
+                    .events
+                    .iter()
+                    .find(|event| event.name == label)
+                    .map(|event| event.time)
+            })
+            .ok_or_else(|| RuntimeError::LabelNotFound(label.to_owned()))?;
+        self.seek(time);
+        Ok(())
     }
-    /// 移除所有帧事件监听器。
-    pub fn clear_all_frame_event_listeners(&mut self) {
-        self.frame_event_listeners.clear();
+
+    /// 前进（负数为后退）指定帧数，循环播放时会在首尾之间回绕
+    pub fn step_frames(&mut self, frames: i32) {
+        let total_frames = self.total_frames() as i32;
+        if total_frames == 0 {
+            return;
+        }
+        let target = self.current_frame() as i32 + frames;
+        let target = if self.loop_mode != LoopMode::Once {
+            target.rem_euclid(total_frames)
+        } else {
+            target.clamp(0, total_frames - 1)
+        };
+        self.seek(target as f32 / self.library.frame_rate());
     }
 
-    pub fn current_animation_name(&self) -> Option<&str> {
-        self.current_animation_name.as_deref()
+    /// 获取指定子影片实例的控制器，可以是实例名（如`eyes`）或实例路径（如`head/eyes`），
+    /// 没有实例名的子影片用深度代替。按实例名控制时会作用于所有同名实例。
+    pub fn clip_mut(&mut self, path: &str) -> Result<ClipController<'_>> {
+        if !self.has_instance_path(path) {
+            return Err(RuntimeError::InstanceNotFound(path.to_owned()).into());
+        }
+        Ok(ClipController {
+            control: self.clip_controls.entry(path.to_owned()).or_default(),
+        })
     }
 
-    pub fn set_speed(&mut self, speed: f32) {
-        self.speed = if speed <= 0.0 { 0.0 } else { speed };
+    /// 取消对子影片实例的控制，恢复自由循环播放
+    pub fn release_clip(&mut self, path: &str) -> bool {
+        self.clip_controls.remove(path).is_some()
     }
 
-    pub fn speed(&self) -> f32 {
-        self.speed
+    /// 粗略判断实例路径是否可能存在：路径中每一段都必须是某个实例名或深度
+    fn has_instance_path(&self, path: &str) -> bool {
+        let has_name = |segment: &str| {
+            self.library
+                .animations()
+                .values()
+                .map(|animation| &animation.timeline)
+                .chain(
+                    self.library
+                        .children_clip()
+                        .values()
+                        .map(MovieClip::timeline),
+                )
+                .flat_map(BTreeMap::values)
+                .flat_map(|depth_timeline| depth_timeline.placement.iter())
+                .any(|placement| {
+                    let clip_name = placement
+                        .resource_id()
+                        .and_then(|id| self.library.clip(id))
+                        .and_then(MovieClip::name);
+                    placement.name().or(clip_name) == Some(segment)
+                })
+        };
+        !path.is_empty()
+            && path
+                .split('/')
+                .all(|segment| segment.parse::<Depth>().is_ok() || has_name(segment))
     }
 
-    pub fn set_looping(&mut self, looping: bool) {
-        self.looping = looping;
+    /// 查询子影片实例在最近一次输出时的世界变换，可以是实例名、链接名或实例路径（如`body/gun/muzzle`）。
+    ///
+    /// 常用于挂点，如枪口、手部位置，子影片中没有可见图形时同样有效。结果与输出的实例一致，
+    /// 包括资源替换和逐帧插值；跳转或切换动画后还没有输出时为下一次输出的变换。
+    /// 有多个同名实例时返回按深度遍历找到的第一个。
+    pub fn instance_transform(&self, path_or_name: &str) -> Option<Matrix> {
+        let animation = self.current_animation()?;
+        let time = self.output_time.unwrap_or(self.current_time);
+        let mut search = TransformSearch::new(path_or_name);
+        search.interpolation = self
+            .output_time
+            .and(self.frame_clock.as_ref())
+            .and_then(|clock| clock.interpolation);
+        self.find_instance_transform(
+            &mut search,
+            ROOT_INSTANCE_ID,
+            "",
+            &animation.timeline,
+            time,
+            self.display.transform(),
+        )
     }
 
-    pub fn looping(&self) -> bool {
-        self.looping
+    /// 同[`Self::instance_transform`]，返回`Mat4`
+    pub fn instance_transform_mat4(&self, path_or_name: &str) -> Option<Mat4> {
+        self.instance_transform(path_or_name).map(Mat4::from)
     }
 
-    pub fn set_playing(&mut self, playing: bool) {
-        self.playing = playing;
+    /// 子影片在最近一次输出时的时间，还没有输出时为下一次输出的时间
+    fn output_clip_time(&self, active: &ActiveClip) -> f32 {
+        match self.output_time {
+            Some(_) => active.output_time,
+            None => active.time,
+        }
     }
 
-    pub fn is_playing(&self) -> bool {
-        self.playing
+    fn find_instance_transform(
+        &self,
+        search: &mut TransformSearch,
+        parent_id: u64,
+        instance_path: &str,
+        timeline: &BTreeMap<Depth, DepthTimeline>,
+        current_time: f32,
+        base_transform: Matrix,
+    ) -> Option<Matrix> {
+        for (depth, depth_timeline) in timeline {
+            let placements = &depth_timeline.placement;
+            let (Some(start_placement), _) = find_key_frame(current_time, placements) else {
+                continue;
+            };
+            let start_keyframe = &placements[start_placement];
+            let Some(id) = start_keyframe.resource_id() else {
+                continue;
+            };
+            // 与输出时一样应用资源替换
+            let id = self
+                .find_override(search, instance_path, *depth, start_keyframe, id)
+                .and_then(|instance_override| instance_override.character)
+                .filter(|character| !search.ancestors.contains(character))
+                .unwrap_or(id);
+            let Some(child_clip) = self.library.clip(id) else {
+                continue;
+            };
+            let (start, end) = find_key_frame(current_time, &depth_timeline.transforms);
+            let Some(start) = start else {
+                continue;
+            };
+            let transform = match search.interpolation {
+                Some(factor) => interpolate_transform(
+                    depth_timeline,
+                    (start, end),
+                    id,
+                    current_time,
+                    factor,
+                    self.library.frame_rate(),
+                ),
+                None => depth_timeline.transforms[start].matrix,
+            };
+            let transform = base_transform * transform;
+
+            let child_name = start_keyframe
+                .name()
+                .or(child_clip.name())
+                .map_or_else(|| depth.to_string(), str::to_owned);
+            let child_path = if instance_path.is_empty() {
+                child_name.clone()
+            } else {
+                format!("{}/{}", instance_path, child_name)
+            };
+            if child_path == search.target
+                || child_name == search.target
+                || child_clip.name() == Some(search.target)
+            {
+                return Some(transform);
+            }
+
+            let placement_index = placement_start(placements, start_placement);
+            let instance_id = stable_instance_id(parent_id, *depth, id, placement_index);
+            let child_time = if child_clip.is_skin_frame() {
+                skin_frame_time(
+                    child_clip,
+                    start_keyframe.name(),
+                    &self.current_skins,
+                    self.library.frame_rate(),
+                )
+            } else {
+                // 还没有遍历过的子影片按放置时间推算
+                self.active_clip.get(&instance_id).map_or_else(
+                    || {
+                        let placement_start = placements[placement_index].time();
+                        ActiveClip::new(child_clip, placement_start, current_time).time
+                    },
+                    |active| self.output_clip_time(active),
+                )
+            };
+            search.ancestors.push(id);
+            let found = self.find_instance_transform(
+                search,
+                instance_id,
+                &child_path,
+                child_clip.timeline(),
+                child_time,
+                transform,
+            );
+            search.ancestors.pop();
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+
+    /// 查找作用于实例的覆盖，规则与输出时一致
+    fn find_override(
+        &self,
+        search: &mut TransformSearch,
+        instance_path: &str,
+        depth: Depth,
+        placement: &Placement,
+        id: CharacterId,
+    ) -> Option<&InstanceOverride> {
+        if self.overrides.is_empty() {
+            return None;
+        }
+        match placement
+            .name()
+            .or_else(|| self.library.clip(id).and_then(MovieClip::name))
+        {
+            Some(name) => find_override(
+                &self.overrides,
+                instance_path,
+                name,
+                true,
+                &mut search.path_buffer,
+            ),
+            None => find_override(
+                &self.overrides,
+                instance_path,
+                &depth.to_string(),
+                false,
+                &mut search.path_buffer,
+            ),
+        }
+    }
+
+    /// 隐藏或显示指定实例，可以是实例名、链接名或实例路径，没有名字的实例用深度代替（如`arm/1`）。
+    ///
+    /// 隐藏的子影片仍然继续播放并触发帧事件。
+    pub fn set_instance_visible(&mut self, path: &str, visible: bool) -> Result<()> {
+        self.instance_override_mut(path)?.hidden = !visible;
+        Ok(())
+    }
+
+    /// 在实例原有的颜色变换上叠加一个颜色变换（乘色和加色），可用于受击闪白、队伍颜色等
+    pub fn set_instance_color_transform(
+        &mut self,
+        path: &str,
+        color_transform: swf::ColorTransform,
+    ) -> Result<()> {
+        self.instance_override_mut(path)?.color_transform = Some(color_transform);
+        Ok(())
+    }
+
+    /// 把实例替换为另一个资源，可以是图形或子影片的id
+    pub fn replace_instance_character(&mut self, path: &str, id: CharacterId) -> Result<()> {
+        self.instance_override_mut(path)?.character = Some(id);
+        Ok(())
+    }
+
+    /// 把实例替换为指定链接名的子影片
+    pub fn replace_instance_symbol(&mut self, path: &str, symbol: &str) -> Result<()> {
+        let Some(clip) = self.library.clip_by_name(symbol) else {
+            return Err(RuntimeError::SymbolNotFound(symbol.to_owned()).into());
+        };
+        let id = clip.id();
+        self.replace_instance_character(path, id)
+    }
+
+    /// 移除指定实例的所有覆盖，返回是否存在覆盖
+    pub fn clear_instance_override(&mut self, path: &str) -> bool {
+        self.overrides.remove(path).is_some()
+    }
+
+    /// 移除所有实例覆盖
+    pub fn clear_instance_overrides(&mut self) {
+        self.overrides.clear();
+    }
+
+    fn instance_override_mut(&mut self, path: &str) -> Result<&mut InstanceOverride> {
+        if !self.has_instance_path(path) {
+            return Err(RuntimeError::InstanceNotFound(path.to_owned()).into());
+        }
+        Ok(self.overrides.entry(path.to_owned()).or_default())
+    }
+
+    /// 设置跳转时是否触发被跳过的帧事件，默认不触发
+    pub fn set_seek_fires_events(&mut self, fires_events: bool) {
+        self.seek_fires_events = fires_events;
+    }
+
+    /// 按当前时间重建所有子影片的播放进度
+    ///
+    /// 仍在显示列表中的子影片保留实例名、滤镜等缓存，只重置播放进度。
+    fn rebuild_active_clip(&mut self) {
+        let Some(animation) = self
+            .current_animation_name
+            .as_ref()
+            .and_then(|name| self.library.animation(name))
+        else {
+            self.active_clip.clear();
+            return;
+        };
+        rebuild_clip_state(
+            ROOT_INSTANCE_ID,
+            &animation.timeline,
+            self.current_time,
+            &mut self.active_clip,
+            self.library.children_clip(),
+        );
+        self.active_clip
+            .retain(|_, active| std::mem::take(&mut active.alive));
+    }
+
+    /// 保存当前的播放状态，包括子影片的播放进度、皮肤、实例覆盖、逐帧播放和显示设置
+    pub fn snapshot(&self) -> PlayerState {
+        let mut clips: Vec<ClipState> = self
+            .active_clip
+            .iter()
+            .map(|(instance_id, active)| ClipState {
+                instance_id: *instance_id,
+                time: active.time,
+                placement_start: active.placement_start,
+                pinned_time: active.pinned_time,
+            })
+            .collect();
+        clips.sort_unstable_by_key(|clip| clip.instance_id);
+        PlayerState {
+            animation: self.current_animation_name.clone(),
+            time: self.current_time,
+            loop_mode: self.loop_mode,
+            completed_loops: self.completed_loops,
+            reversed: self.reversed,
+            speed: self.speed,
+            playing: self.playing,
+            skins: self
+                .current_skins
+                .iter()
+                .map(|(part, skin)| (part.clone(), skin.clone()))
+                .collect(),
+            clips,
+            clip_controls: self
+                .clip_controls
+                .iter()
+                .map(|(path, control)| (path.clone(), control.into()))
+                .collect(),
+            overrides: self
+                .overrides
+                .iter()
+                .map(|(path, instance_override)| (path.clone(), instance_override.into()))
+                .collect(),
+            frame_clock: self.frame_clock.as_ref().map(Into::into),
+            frame_interpolation: self.frame_interpolation,
+            display: (&self.display).into(),
+        }
+    }
+
+    /// 恢复到快照时的播放状态，之后的输出与保存快照的播放器完全一致。
+    ///
+    /// 不会触发事件；动画与当前不同时清除完成回调。根运动从恢复后的位置重新开始计算。
+    pub fn restore(&mut self, state: &PlayerState) -> Result<()> {
+        if let Some(name) = &state.animation
+            && self.library.animation(name).is_none()
+        {
+            return Err(RuntimeError::AnimationNotFound(name.clone()).into());
+        }
+        if self.current_animation_name != state.animation {
+            self.on_completion = None;
+        }
+        self.current_animation_name.clone_from(&state.animation);
+        self.current_time = state.time;
+        self.output_time = None;
+        self.loop_mode = state.loop_mode;
+        self.completed_loops = state.completed_loops;
+        self.reversed = state.reversed;
+        self.speed = state.speed;
+        self.playing = state.playing;
+        self.current_skins = state
+            .skins
+            .iter()
+            .map(|(part, skin)| (part.clone(), skin.clone()))
+            .collect();
+        self.active_clip = state
+            .clips
+            .iter()
+            .map(|clip| {
+                let mut active = ActiveClip::restored(clip.time, clip.placement_start);
+                active.pinned_time = clip.pinned_time;
+                (clip.instance_id, active)
+            })
+            .collect();
+        self.clip_controls = state
+            .clip_controls
+            .iter()
+            .map(|(path, control)| (path.clone(), control.into()))
+            .collect();
+        self.overrides = state
+            .overrides
+            .iter()
+            .map(|(path, instance_override)| (path.clone(), instance_override.into()))
+            .collect();
+        self.frame_clock = state.frame_clock.as_ref().map(Into::into);
+        self.frame_interpolation = state.frame_interpolation;
+        self.display = (&state.display).into();
+        self.active_instances.clear();
+        self.needs_redraw = true;
+        if let Some(root_motion) = self.root_motion.as_mut() {
+            root_motion.rebase();
+        }
+        Ok(())
+    }
+
+    pub fn active_instances(&self) -> &Vec<RuntimeInstance> {
+        &self.active_instances
+    }
+
+    pub fn animation_names(&self) -> Vec<&String> {
+        self.library.animation_names()
+    }
+
+    /// 设置播放动画
+    /// - name 动画名
+    /// - loop_mode 循环模式，也可以直接传入`bool`表示是否循环播放
+    /// - on_completion 回调事件，最后一轮播放结束时触发
+    ///
+    /// 播放速度为负数时从动画末尾开始倒放。
+    pub fn set_play_animation(
+        &mut self,
+        name: &str,
+        loop_mode: impl Into<LoopMode>,
+        on_completion: Option<CompletionCallback>,
+    ) -> Result<()> {
+        let Some(animation) = self.library.animation(name) else {
+            return Err(RuntimeError::AnimationNotFound(name.to_owned()).into());
+        };
+
+        // 重置时间
+        self.current_time = if self.speed < 0.0 {
+            animation.duration
+        } else {
+            0.0
+        };
+        self.output_time = None;
+        // 清除活动实例
+        self.active_instances.clear();
+        // 子影片从头开始播放
+        self.active_clip.clear();
+
+        self.current_animation_name = Some(name.to_owned());
+        self.loop_mode = loop_mode.into();
+        self.completed_loops = 0;
+        self.reversed = false;
+        self.on_completion = on_completion;
+        if let Some(root_motion) = self.root_motion.as_mut() {
+            root_motion.reset();
+        }
+        if self.event_queue_enabled {
+            self.event_queue.push(PlayerEvent::Started {
+                animation: name.to_owned(),
+            });
+        }
+        Ok(())
+    }
+
+    /// 设置皮肤
+    /// - part_name 部位名
+    /// - skin_name 皮肤名
+    pub fn set_skin(&mut self, part_name: &str, skin_name: &str) -> Result<()> {
+        self.validate_skin(part_name, skin_name)?;
+        self.current_skins
+            .insert(part_name.to_owned(), skin_name.to_owned());
+        Ok(())
+    }
+
+    /// 一次设置多个部位的皮肤，任意一个部位或皮肤不存在时都不会生效
+    pub fn set_skins<'s>(
+        &mut self,
+        skins: impl IntoIterator<Item = (&'s str, &'s str)> + Clone,
+    ) -> Result<()> {
+        for (part_name, skin_name) in skins.clone() {
+            self.validate_skin(part_name, skin_name)?;
+        }
+        for (part_name, skin_name) in skins {
+            self.current_skins
+                .insert(part_name.to_owned(), skin_name.to_owned());
+        }
+        Ok(())
+    }
+
+    /// 应用皮肤套装。套装来自数据中的`skin_sets`或子影片中的`outfit_`标签。
+    ///
+    /// 套装中不存在的部位会被忽略，部位没有对应的皮肤时恢复为默认皮肤。
+    pub fn apply_skin_set(&mut self, name: &str) -> Result<()> {
+        let catalog = self.library.skin_catalog();
+        let Some(skin_set) = catalog.skin_set(name) else {
+            return Err(RuntimeError::SkinSetNotFound(name.to_owned()).into());
+        };
+        for (part_name, skin_name) in skin_set {
+            if !catalog.has_part(part_name) {
+                warn!("皮肤套装`{}`中的部位不存在: {}", name, part_name);
+            } else if catalog.has_skin(part_name, skin_name) {
+                self.current_skins
+                    .insert(part_name.clone(), skin_name.clone());
+            } else {
+                warn!(
+                    "皮肤套装`{}`中部位`{}`没有皮肤`{}`，使用默认皮肤",
+                    name, part_name, skin_name
+                );
+                self.current_skins.remove(part_name);
+            }
+        }
+        Ok(())
+    }
+
+    /// 所有部位恢复为默认皮肤
+    pub fn reset_skins(&mut self) {
+        self.current_skins.clear();
+    }
+
+    /// 可换肤的部位、皮肤和套装
+    pub fn skin_catalog(&self) -> &SkinCatalog {
+        self.library.skin_catalog()
+    }
+
+    fn validate_skin(&self, part_name: &str, skin_name: &str) -> Result<()> {
+        let catalog = self.library.skin_catalog();
+        if !catalog.has_part(part_name) {
+            return Err(RuntimeError::SkinPartNotFound(part_name.to_owned()).into());
+        }
+        if !catalog.has_skin(part_name, skin_name) {
+            return Err(RuntimeError::SkinNotFound(skin_name.to_owned()).into());
+        }
+        Ok(())
+    }
+
+    #[deprecated(note = "请使用`skin_catalog`，部位按实例名区分且查询时不分配内存")]
+    pub fn get_skips(&self) -> Vec<HashMap<&str, Vec<&String>>> {
+        self.library
+            .skin_catalog()
+            .parts()
+            .map(|part| {
+                let skins = self.library.skin_catalog().skins(part).unwrap_or_default();
+                HashMap::from([(part, skins.iter().collect())])
+            })
+            .collect()
+    }
+
+    pub fn current_skins(&self) -> &HashMap<String, String> {
+        &self.current_skins
+    }
+
+    /// 注册一个监听特定名称帧事件的回调函数，返回的句柄可用于单独移除这个监听器。
+    ///
+    /// 事件必须在这个动画或其时间轴上（包括嵌套）放置的子影片中。
+    ///
+    /// # Arguments
+    /// * `animation_name` - 要监听的动画名，只有播放这个动画时才会触发。
+    /// * `event_name` - 要监听的事件名称 (例如 "footstep", "hit_impact")。
+    /// * `callback` - 当事件触发时要调用的函数。
+    pub fn register_frame_event<T>(
+        &mut self,
+        animation_name: &str,
+        event_name: String,
+        callback: T,
+    ) -> Result<ListenerId>
+    where
+        T: Fn() + Send + Sync + 'static,
+    {
+        // 判断监听的事件是否存在，子影片上的事件也可以监听
+        let Some(animation) = self.library.animation(animation_name) else {
+            return Err(RuntimeError::AnimationNotFound(animation_name.to_owned()).into());
+        };
+        if !animation
+            .events
+            .iter()
+            .any(|event| event.name == event_name)
+            && !timeline_has_event(
+                &self.library,
+                &animation.timeline,
+                &event_name,
+                &mut HashSet::new(),
+            )
+        {
+            return Err(RuntimeError::AnimationEventNotFound(event_name).into());
+        }
+
+        let id = ListenerId(self.next_listener_id);
+        self.next_listener_id += 1;
+        self.frame_event_listeners
+            .entry(event_name)
+            .or_default()
+            .push(FrameEventListener {
+                id,
+                animation: animation_name.to_owned(),
+                callback: Box::new(callback),
+            });
+        Ok(id)
+    }
+
+    /// 移除单个帧事件监听器，返回是否找到了该监听器。
+    pub fn remove_frame_event_listener(&mut self, id: ListenerId) -> bool {
+        let mut removed = false;
+        self.frame_event_listeners.retain(|_, listeners| {
+            let len = listeners.len();
+            listeners.retain(|listener| listener.id != id);
+            removed |= listeners.len() != len;
+            !listeners.is_empty()
+        });
+        removed
+    }
+
+    /// 移除指定事件名称的所有监听器。
+    pub fn clear_frame_event_listeners(&mut self, event_name: &str) {
+        self.frame_event_listeners.remove(event_name);
+    }
+    /// 移除所有帧事件监听器。
+    pub fn clear_all_frame_event_listeners(&mut self) {
+        self.frame_event_listeners.clear();
+    }
+
+    /// 设置是否把事件收集到队列中，供[`Self::drain_events`]拉取。
+    ///
+    /// 默认关闭，开启后需要定期拉取，否则队列会一直增长。关闭时会清空队列。
+    pub fn set_event_queue_enabled(&mut self, enabled: bool) {
+        self.event_queue_enabled = enabled;
+        if !enabled {
+            self.event_queue.clear();
+        }
+    }
+
+    /// 取出上次拉取以来产生的所有事件，按发生顺序排列
+    pub fn drain_events(&mut self) -> Vec<PlayerEvent> {
+        std::mem::take(&mut self.event_queue)
+    }
+
+    /// 设置是否计算相邻两次输出之间的实例差异，默认关闭
+    pub fn set_instance_diff_enabled(&mut self, enabled: bool) {
+        if enabled != self.instance_diff.is_some() {
+            self.instance_diff = enabled.then(DiffTracker::default);
+        }
+    }
+
+    /// 最近一次输出相对上一次输出的实例差异，未开启时返回`None`
+    pub fn instance_diff(&self) -> Option<&InstanceDiff> {
+        self.instance_diff.as_ref().map(DiffTracker::diff)
+    }
+
+    pub fn current_animation_name(&self) -> Option<&str> {
+        self.current_animation_name.as_deref()
+    }
+
+    /// 设置播放速度，负数为倒放
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = if speed.is_finite() { speed } else { 0.0 };
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn set_loop_mode(&mut self, loop_mode: LoopMode) {
+        self.loop_mode = loop_mode;
+    }
+
+    pub fn loop_mode(&self) -> LoopMode {
+        self.loop_mode
+    }
+
+    #[deprecated(note = "请使用`set_loop_mode`")]
+    pub fn set_looping(&mut self, looping: bool) {
+        self.set_loop_mode(looping.into());
+    }
+
+    /// 除了只播放一次，其它循环模式都视为循环
+    #[deprecated(note = "请使用`loop_mode`")]
+    pub fn looping(&self) -> bool {
+        self.loop_mode != LoopMode::Once
+    }
+
+    /// 当前动画已经完成的循环次数
+    pub fn completed_loops(&self) -> u32 {
+        self.completed_loops
+    }
+
+    pub fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+}
+
+/// 遍历时间轴收集活动实例时共享的上下文
+struct Collector<'a> {
+    children_clip: &'a HashMap<CharacterId, MovieClip>,
+    active_clip: &'a mut HashMap<u64, ActiveClip>,
+    clip_controls: &'a mut HashMap<String, ClipControl>,
+    overrides: &'a HashMap<String, InstanceOverride>,
+    current_skins: &'a HashMap<String, String>,
+    frame_rate: f32,
+    /// 子影片本次推进的时间
+    elapsed_time: f32,
+    /// 输出的实例，从头覆盖上一次的输出，遍历结束后截断到`instance_count`
+    active_instances: &'a mut Vec<RuntimeInstance>,
+    /// 本次遍历已经输出的实例数量
+    instance_count: usize,
+    buffers: &'a mut CollectBuffers,
+    /// 为`None`时不收集子影片的帧事件
+    events: Option<EventSink<'a>>,
+    /// 子影片的帧事件监听器
+    listeners: &'a HashMap<String, Vec<FrameEventListener>>,
+    animation_name: &'a str,
+    root_motion_source: Option<&'a RootMotionSource>,
+    /// 本次遍历找到的根运动参照的世界变换
+    root_motion_reference: Option<Matrix>,
+    /// 逐帧播放时到下一帧的插值因子
+    interpolation: Option<f32>,
+}
+
+/// 查询子影片世界变换时沿时间轴向下传递的状态
+struct TransformSearch<'a> {
+    /// 要查找的实例名、链接名或实例路径
+    target: &'a str,
+    /// 从根到当前时间轴的子影片资源id，替换为自身的祖先会无限嵌套，忽略这种替换
+    ancestors: Vec<CharacterId>,
+    /// 到下一帧的插值因子
+    interpolation: Option<f32>,
+    /// 查找实例覆盖时拼接的实例路径
+    path_buffer: String,
+}
+
+impl<'a> TransformSearch<'a> {
+    fn new(target: &'a str) -> Self {
+        Self {
+            target,
+            ancestors: Vec::new(),
+            interpolation: None,
+            path_buffer: String::new(),
+        }
+    }
+}
+
+/// 遍历时复用的缓冲区，播放稳定后不再分配
+#[derive(Debug, Default)]
+struct CollectBuffers {
+    /// 从根到当前时间轴上生效的遮罩，值为（遮罩的最大深度，遮罩层id）
+    masks: Vec<(Depth, u64)>,
+    /// 查找实例覆盖时拼接的实例路径
+    override_path: String,
+    /// 没有实例名时代替实例名的深度
+    depth_name: String,
+}
+
+/// 继承的遮罩加上当前时间轴上生效的遮罩，内容与`previous`相同时复用它
+fn inherit_masks(
+    previous: Option<&Arc<[u64]>>,
+    inherited: &Arc<[u64]>,
+    masks: &[(Depth, u64)],
+) -> Arc<[u64]> {
+    if masks.is_empty() {
+        return inherited.clone();
+    }
+    let masked_by = || {
+        inherited
+            .iter()
+            .copied()
+            .chain(masks.iter().map(|(_, mask)| *mask))
+    };
+    match previous {
+        Some(previous)
+            if previous.len() == inherited.len() + masks.len()
+                && previous.iter().copied().eq(masked_by()) =>
+        {
+            previous.clone()
+        }
+        _ => masked_by().collect(),
+    }
+}
+
+/// 从父级继承的显示属性
+#[derive(Clone)]
+struct Inherited<'f> {
+    transform: Matrix,
+    color_transform: swf::ColorTransform,
+    blend_mode: BlendMode,
+    /// 借用自父级子影片缓存的滤镜列表
+    filters: &'f [RenderFilter],
+    ancestors: Arc<[InstanceAncestor]>,
+    /// 被运行时覆盖隐藏
+    hidden: bool,
+    /// 所在的遮罩层
+    mask_layer: Option<u64>,
+    /// 遮住这个实例的遮罩层
+    masked_by: Arc<[u64]>,
+}
+
+impl Default for Inherited<'_> {
+    fn default() -> Self {
+        Self {
+            transform: Matrix::IDENTITY,
+            color_transform: swf::ColorTransform::IDENTITY,
+            blend_mode: BlendMode::Normal,
+            filters: &[],
+            ancestors: Arc::default(),
+            hidden: false,
+            mask_layer: None,
+            masked_by: Arc::default(),
+        }
+    }
+}
+
+impl<'a> Collector<'a> {
+    fn collect(
+        &mut self,
+        parent_id: u64,
+        instance_path: &str,
+        timeline: &BTreeMap<u16, DepthTimeline>,
+        current_time: f32,
+        base: Inherited,
+    ) -> Result<()> {
+        // 当前时间轴上生效的遮罩在遮罩栈中的起始位置，子时间轴返回前会恢复栈
+        let mask_base = self.buffers.masks.len();
+        for (depth, depth_timeline) in timeline {
+            let mut index = mask_base;
+            while index < self.buffers.masks.len() {
+                if *depth > self.buffers.masks[index].0 {
+                    self.buffers.masks.remove(index);
+                } else {
+                    index += 1;
+                }
+            }
+            let placements = &depth_timeline.placement;
+            let (Some(start_placement), _end_placement) = find_key_frame(current_time, placements)
+            else {
+                continue;
+            };
+
+            let start_keyframe = placements.get(start_placement).unwrap();
+            let Some(id) = start_keyframe.resource_id() else {
+                continue;
+            };
+            let instance_override = self.find_override(instance_path, *depth, start_keyframe, id);
+            // 替换为自身的祖先会无限嵌套，忽略这种替换
+            let id = instance_override
+                .and_then(|instance_override| instance_override.character)
+                .filter(|character| {
+                    base.ancestors
+                        .iter()
+                        .all(|ancestor| ancestor.character_id != *character)
+                })
+                .unwrap_or(id);
+            let hidden = base.hidden || instance_override.is_some_and(|o| o.hidden);
+            // 唯一标识，同一时间轴位置上的同一资源每一帧都得到相同的id
+            let placement_index = placement_start(placements, start_placement);
+            let instance_id = stable_instance_id(parent_id, *depth, id, placement_index);
+            let children_clip: &'a HashMap<CharacterId, MovieClip> = self.children_clip;
+            let child_clip = children_clip.get(&id);
+
+            // 遮罩，内容不变时复用上一次的遮罩列表
+            let previous_masked_by = match child_clip {
+                Some(_) => self
+                    .active_clip
+                    .get(&instance_id)
+                    .and_then(|active| active.masked_by.as_ref()),
+                None => self
+                    .active_instances
+                    .get(self.instance_count)
+                    .map(|instance| &instance.masked_by),
+            };
+            let masked_by = inherit_masks(
+                previous_masked_by,
+                &base.masked_by,
+                &self.buffers.masks[mask_base..],
+            );
+            let mask_layer = match start_keyframe.clip_depth() {
+                Some(clip_depth) => {
+                    self.buffers.masks.push((clip_depth, instance_id));
+                    Some(instance_id)
+                }
+                None => base.mask_layer,
+            };
+
+            let transforms = &depth_timeline.transforms;
+            // 既然start存在那么transform一定存在
+            let (start, end) = find_key_frame(current_time, transforms);
+            let start = start.unwrap();
+            let transform = match self.interpolation {
+                Some(factor) => interpolate_transform(
+                    depth_timeline,
+                    (start, end),
+                    id,
+                    current_time,
+                    factor,
+                    self.frame_rate,
+                ),
+                None => transforms[start].matrix,
+            };
+            let current_transform = base.transform * transform;
+            if self.root_motion_reference.is_none()
+                && parent_id == ROOT_INSTANCE_ID
+                && self.root_motion_source == Some(&RootMotionSource::Root)
+            {
+                self.root_motion_reference = Some(current_transform);
+            }
+
+            // 颜色变换
+            let color_transform = start_keyframe.color_transform().color_transform;
+            let mut current_color_transform = base.color_transform * color_transform;
+            if let Some(color_transform) = instance_override.and_then(|o| o.color_transform) {
+                current_color_transform *= color_transform;
+            }
+
+            let Some(child_clip) = child_clip else {
+                if hidden {
+                    continue;
+                }
+                // 记录这个child_movie找到的shape为当前活动实例，将每一帧的实例Shape扁平化输出，游戏引擎中迭代实在不方便
+                let draw_order = self.instance_count as u32;
+                let instance = RuntimeInstance {
+                    id,
+                    instance_id,
+                    depth: *depth,
+                    ancestors: base.ancestors.clone(),
+                    draw_order,
+                    transform: current_transform,
+                    color_transform: current_color_transform,
+                    blend: base.blend_mode,
+                    filters: Vec::new(),
+                    mask_layer,
+                    masked_by,
+                };
+                // 覆盖上一次输出的同一位置，复用滤镜列表的空间
+                match self.active_instances.get_mut(self.instance_count) {
+                    Some(slot) => {
+                        let mut filters = std::mem::take(&mut slot.filters);
+                        base.filters.clone_into(&mut filters);
+                        *slot = RuntimeInstance {
+                            filters,
+                            ..instance
+                        };
+                    }
+                    None => self.active_instances.push(RuntimeInstance {
+                        filters: base.filters.to_vec(),
+                        ..instance
+                    }),
+                }
+                self.instance_count += 1;
+                continue;
+            };
+
+            // 混合模式
+            let blend_mode = start_keyframe.blend_mode();
+
+            // 同一深度被重新放置（Place/Replace）时视为新的实例，从第一帧开始播放
+            let placement_start = placements[placement_index].time();
+            let mut active = match self.active_clip.remove(&instance_id) {
+                Some(active) if active.placement_start == placement_start => active,
+                _ => ActiveClip::new(child_clip, placement_start, current_time),
+            };
+
+            // 实例路径，没有实例名的子影片用深度代替
+            let name = start_keyframe.name().or(child_clip.name());
+            if !active.has_name(name, *depth, instance_path) {
+                active.set_name(name, *depth, instance_path);
+                // 实例名变化后祖先链也需要重新创建
+                active.ancestors = None;
+            }
+            // 祖先链只在实例名、自身的滤镜数量、混合模式或父级的祖先链变化时重新创建
+            let filter_count = start_keyframe.filters().len();
+            if !Arc::ptr_eq(&active.parent_ancestors, &base.ancestors)
+                || active
+                    .ancestors
+                    .as_ref()
+                    .and_then(|ancestors| ancestors.last())
+                    .is_some_and(|ancestor| {
+                        ancestor.filter_count != filter_count || ancestor.blend_mode != blend_mode
+                    })
+            {
+                active.parent_ancestors = base.ancestors.clone();
+                active.ancestors = None;
+            }
+            let ancestors = active
+                .ancestors
+                .get_or_insert_with(|| {
+                    base.ancestors
+                        .iter()
+                        .cloned()
+                        .chain(std::iter::once(InstanceAncestor {
+                            instance_id,
+                            depth: *depth,
+                            character_id: id,
+                            name: start_keyframe.name().map(str::to_owned),
+                            linkage_name: child_clip.name().map(str::to_owned),
+                            filter_count,
+                            blend_mode,
+                        }))
+                        .collect()
+                })
+                .clone();
+            // 滤镜，关键帧和继承的滤镜都没有变化时复用上一次的结果
+            active.update_filters(start_placement, start_keyframe, base.filters);
+            active.masked_by = Some(masked_by.clone());
+            let child_name = active.name.as_str();
+            let child_path = active.path.as_str();
+
+            if self.root_motion_reference.is_none()
+                && let Some(RootMotionSource::Instance(name)) = self.root_motion_source
+                && (*name == child_path || *name == child_name || child_clip.name() == Some(name))
+            {
+                self.root_motion_reference = Some(current_transform);
+            }
+
+            // 判断是否是皮肤clip
+            let mut child_time = active.time;
+            let child_current_time = if child_clip.is_skin_frame() {
+                skin_frame_time(
+                    child_clip,
+                    start_keyframe.name(),
+                    self.current_skins,
+                    self.frame_rate,
+                )
+            } else {
+                child_time
+            };
+
+            // 被单独控制的子影片
+            let mut child_elapsed = self.elapsed_time;
+            let control_key = [child_path, child_name]
+                .into_iter()
+                .find(|key| self.clip_controls.contains_key(*key));
+            let child_current_time = match control_key {
+                Some(key) if !child_clip.is_skin_frame() => {
+                    let control = self.clip_controls.get_mut(key).unwrap();
+                    apply_clip_control(
+                        control,
+                        &mut active.pinned_time,
+                        child_clip,
+                        &mut child_time,
+                        &mut child_elapsed,
+                        self.frame_rate,
+                    );
+                    child_time
+                }
+                _ => child_current_time,
+            };
+
+            active.output_time = child_current_time;
+            self.collect(
+                instance_id,
+                child_path,
+                child_clip.timeline(),
+                child_current_time,
+                Inherited {
+                    transform: current_transform,
+                    color_transform: current_color_transform,
+                    blend_mode,
+                    filters: &active.filters,
+                    ancestors,
+                    hidden,
+                    mask_layer,
+                    masked_by,
+                },
+            )?;
+            if !child_clip.is_skin_frame() {
+                self.dispatch_clip_events(child_clip, child_time, child_path, child_elapsed);
+            }
+            child_time += child_elapsed;
+            if !(0.0..child_clip.duration()).contains(&child_time) {
+                child_time = child_time.rem_euclid(child_clip.duration());
+            }
+            active.time = child_time;
+            active.alive = true;
+            self.active_clip.insert(instance_id, active);
+        }
+        self.buffers.masks.truncate(mask_base);
+        Ok(())
+    }
+
+    /// 查找作用于这个深度上实例的覆盖，实例名规则与实例路径一致
+    fn find_override(
+        &mut self,
+        instance_path: &str,
+        depth: Depth,
+        placement: &Placement,
+        id: CharacterId,
+    ) -> Option<&'a InstanceOverride> {
+        let overrides: &'a HashMap<String, InstanceOverride> = self.overrides;
+        if overrides.is_empty() {
+            return None;
+        }
+        let buffers = &mut *self.buffers;
+        match placement
+            .name()
+            .or_else(|| self.children_clip.get(&id).and_then(MovieClip::name))
+        {
+            Some(name) => find_override(
+                overrides,
+                instance_path,
+                name,
+                true,
+                &mut buffers.override_path,
+            ),
+            None => {
+                buffers.depth_name.clear();
+                let _ = write!(buffers.depth_name, "{depth}");
+                find_override(
+                    overrides,
+                    instance_path,
+                    &buffers.depth_name,
+                    false,
+                    &mut buffers.override_path,
+                )
+            }
+        }
+    }
+
+    /// 子影片推进`elapsed_time`时经过的帧事件，调用监听器并进入事件队列
+    fn dispatch_clip_events(
+        &mut self,
+        clip: &MovieClip,
+        current_time: f32,
+        instance_path: &str,
+        elapsed_time: f32,
+    ) {
+        if self.events.is_none() && self.listeners.is_empty() {
+            return;
+        }
+        let duration = clip.duration();
+        if clip.events().is_empty() || duration <= 0.0 || elapsed_time == 0.0 {
+            return;
+        }
+
+        let forward = elapsed_time > 0.0;
+        let (boundary, opposite) = if forward {
+            (duration, 0.0)
+        } else {
+            (0.0, duration)
+        };
+        // 子影片的推进量换算成根时间轴的推进量
+        let scale = self.elapsed_time.abs() / elapsed_time.abs();
+        let mut time = current_time;
+        let mut remaining = elapsed_time.abs();
+        loop {
+            let consumed = elapsed_time.abs() - remaining;
+            let target = if forward {
+                time + remaining
+            } else {
+                time - remaining
+            };
+            let crossed = if forward {
+                target >= duration
+            } else {
+                target <= 0.0
+            };
+            let mut segment = TimeSegment::new(time, if crossed { boundary } else { target });
+            segment.inclusive_end = crossed && !forward;
+
+            let passed = clip
+                .events()
+                .iter()
+                .filter(|event| segment.passes(event.time, self.frame_rate));
+            let mut push = |event: &Event| {
+                notify_listeners(self.listeners, self.animation_name, &event.name);
+                let Some(sink) = self.events.as_mut() else {
+                    return;
+                };
+                let event_progress = (consumed + (event.time - time).abs()) * scale;
+                sink.push(
+                    PlayerEvent::FrameEvent {
+                        animation: self.animation_name.to_owned(),
+                        name: event.name.clone(),
+                        payload: event.payload.clone(),
+                        instance_path: instance_path.to_owned(),
+                    },
+                    event_progress,
+                );
+            };
+            if forward {
+                passed.for_each(&mut push);
+            } else {
+                passed.rev().for_each(&mut push);
+            }
+
+            if !crossed {
+                break;
+            }
+            remaining = (target - boundary).abs();
+            time = opposite;
+            // 跨过很多轮时只派发最后几轮的事件，与根时间轴的推进一致
+            if remaining >= duration * (MAX_FULL_LOOPS + 1) as f32 {
+                remaining = duration * MAX_FULL_LOOPS as f32 + remaining % duration;
+            }
+        }
+    }
+}
+
+/// 插值到下一帧的变换。
+///
+/// 关键帧之间的变换是离散的，只在下一个关键帧恰好位于下一帧、且下一帧仍是同一个实例时插值。
+fn interpolate_transform(
+    depth_timeline: &DepthTimeline,
+    (start, end): (usize, Option<usize>),
+    id: CharacterId,
+    current_time: f32,
+    factor: f32,
+    frame_rate: f32,
+) -> Matrix {
+    let transforms = &depth_timeline.transforms;
+    let placements = &depth_timeline.placement;
+    let matrix = transforms[start].matrix;
+    // 刚好落在关键帧上时没有结束关键帧
+    let Some(next) = end.or_else(|| (start + 1 < transforms.len()).then_some(start + 1)) else {
+        return matrix;
+    };
+    let next_time = transforms[next].time;
+    if next_time > current_time + 1.0 / frame_rate + FRAME_EPSILON {
+        return matrix;
+    }
+    let same_instance = match find_key_frame(next_time, placements).0 {
+        Some(index) => {
+            let placement = &placements[index];
+            placement.resource_id() == Some(id)
+                && !(placement.is_new_instance() && placement.time() > current_time)
+        }
+        None => false,
+    };
+    if same_instance {
+        lerp_matrix(&matrix, &transforms[next].matrix, factor)
+    } else {
+        matrix
+    }
+}
+
+/// 皮肤子影片停留的时间，先按实例名查找设置的皮肤，再按子影片的链接名查找
+/// 时间轴上放置的子影片（包括嵌套）中是否有名为`name`的事件，`visited`记录已经检查过的子影片
+fn timeline_has_event(
+    library: &AnimationLibrary,
+    timeline: &BTreeMap<Depth, DepthTimeline>,
+    name: &str,
+    visited: &mut HashSet<CharacterId>,
+) -> bool {
+    timeline
+        .values()
+        .flat_map(|depth_timeline| &depth_timeline.placement)
+        .filter_map(Placement::resource_id)
+        .any(|id| {
+            visited.insert(id)
+                && library.clip(id).is_some_and(|clip| {
+                    clip.events().iter().any(|event| event.name == name)
+                        || timeline_has_event(library, clip.timeline(), name, visited)
+                })
+        })
+}
+
+fn skin_frame_time(
+    clip: &MovieClip,
+    instance_name: Option<&str>,
+    current_skins: &HashMap<String, String>,
+    frame_rate: f32,
+) -> f32 {
+    let name = clip.name().expect("替换皮肤的影片剪辑必须命名！");
+    // 是否设置了皮肤，同名部位的其它子影片可能没有这个皮肤，这时使用默认皮肤
+    let skip_frame = instance_name
+        .and_then(|instance_name| current_skins.get(instance_name))
+        .or_else(|| current_skins.get(name))
+        .and_then(|skip_name| clip.skin_frame(skip_name))
+        .copied()
+        .unwrap_or_else(|| clip.default_skin_frame());
+    // 计算对应帧对应的事件
+    skip_frame as f32 / frame_rate
+}
+
+/// 应用子影片的播放控制，修正其当前时间和本次推进的时间，`pinned_time`为这个实例停止时固定的时间
+fn apply_clip_control(
+    control: &mut ClipControl,
+    pinned_time: &mut Option<f32>,
+    clip: &MovieClip,
+    current_time: &mut f32,
+    elapsed_time: &mut f32,
+    frame_rate: f32,
+) {
+    if let Some(target) = &control.goto {
+        // 同名的实例都需要跳转，遍历结束后才清除
+        control.goto_applied = true;
+        *pinned_time = None;
+        match target.resolve(clip, frame_rate) {
+            Some(time) => *current_time = time,
+            None => warn!("子影片跳转目标不存在: {:?}", target),
+        }
+    }
+    if control.playing {
+        *pinned_time = None;
+        *elapsed_time *= control.speed;
+    } else {
+        *current_time = *pinned_time.get_or_insert(*current_time);
+        *elapsed_time = 0.0;
+    }
+}
+
+/// 显示列表中的子影片实例，只记录播放进度，时间轴数据从资源中读取
+struct ActiveClip {
+    /// 子影片的当前时间
+    time: f32,
+    /// 最近一次输出时子影片的时间
+    output_time: f32,
+    /// 被控制停止时固定的时间
+    pinned_time: Option<f32>,
+    /// 当前放置开始的时间，用于判断是否被重新放置
+    placement_start: f32,
+    /// 从根到这个子影片的祖先链，包括自身
+    ancestors: Option<Arc<[InstanceAncestor]>>,
+    /// 创建`ancestors`时父级的祖先链，父级重新创建祖先链后这里也需要重新创建
+    parent_ancestors: Arc<[InstanceAncestor]>,
+    /// 本次遍历时是否仍在显示列表中
+    alive: bool,
+    /// 实例名，没有实例名时为深度
+    name: String,
+    /// 实例路径
+    path: String,
+    /// 自身关键帧的滤镜加上继承的滤镜，传给子时间轴
+    filters: Vec<RenderFilter>,
+    /// 生成`filters`时的放置关键帧
+    filters_placement: Option<usize>,
+    /// 遮住这个子影片的遮罩层
+    masked_by: Option<Arc<[u64]>>,
+}
+
+impl ActiveClip {
+    /// 按放置开始的时间推算子影片在`current_time`时的播放进度，结果与逐帧累加保持一致
+    fn new(clip: &MovieClip, placement_start: f32, current_time: f32) -> Self {
+        let elapsed = current_time - placement_start;
+        let time = if clip.duration() > 0.0 {
+            elapsed.rem_euclid(clip.duration())
+        } else {
+            0.0
+        };
+        Self::restored(time, placement_start)
+    }
+
+    /// 只有播放进度的子影片，其余在下一次遍历时生成
+    fn restored(time: f32, placement_start: f32) -> Self {
+        Self {
+            time,
+            output_time: time,
+            pinned_time: None,
+            placement_start,
+            ancestors: None,
+            parent_ancestors: Arc::default(),
+            alive: false,
+            name: String::new(),
+            path: String::new(),
+            filters: Vec::new(),
+            filters_placement: None,
+            masked_by: None,
+        }
+    }
+
+    /// 实例名和实例路径是否与记录的一致，避免每次遍历重新拼接
+    fn has_name(&self, name: Option<&str>, depth: Depth, parent_path: &str) -> bool {
+        let name_matches = match name {
+            Some(name) => self.name == name,
+            None => self.name.parse() == Ok(depth),
+        };
+        let path_matches = if parent_path.is_empty() {
+            self.path == self.name
+        } else {
+            self.path.len() == parent_path.len() + 1 + self.name.len()
+                && self.path.starts_with(parent_path)
+                && self.path[parent_path.len()..].starts_with('/')
+                && self.path.ends_with(&self.name)
+        };
+        name_matches && path_matches
+    }
+
+    fn set_name(&mut self, name: Option<&str>, depth: Depth, parent_path: &str) {
+        self.name.clear();
+        match name {
+            Some(name) => self.name.push_str(name),
+            None => {
+                let _ = write!(self.name, "{depth}");
+            }
+        }
+        self.path.clear();
+        if !parent_path.is_empty() {
+            self.path.push_str(parent_path);
+            self.path.push('/');
+        }
+        self.path.push_str(&self.name);
+    }
+
+    /// 放置关键帧没有变化、继承的滤镜也相同时保留上一次的滤镜列表
+    fn update_filters(&mut self, index: usize, placement: &Placement, inherited: &[RenderFilter]) {
+        let own = placement.filters().len();
+        if self.filters_placement == Some(index)
+            && self.filters.len() == own + inherited.len()
+            && self.filters[own..] == *inherited
+        {
+            return;
+        }
+        self.filters.clear();
+        self.filters
+            .extend(placement.filters().iter().map(RenderFilter::from));
+        self.filters.extend_from_slice(inherited);
+        self.filters_placement = Some(index);
+    }
+}
+
+/// 一次推进最多逐轮处理的完整轮次，更多的轮次直接跳过，不派发其中的事件
+const MAX_FULL_LOOPS: u32 = 3;
+
+/// 按循环模式推进时间，结果写入`advance`以复用其空间，结束时`finished`为真，时间停在边界上
+fn advance_clock(
+    advance: &mut TimeAdvance,
+    current_time: &mut f32,
+    loop_mode: LoopMode,
+    completed_loops: &mut u32,
+    reversed: &mut bool,
+    elapsed_time: f32,
+    duration: f32,
+) {
+    advance.segments.clear();
+    advance.boundaries = 0;
+    advance.skipped_loops = 0;
+    advance.wrapped = false;
+    advance.finished = false;
+    let mut time = *current_time;
+    let mut remaining = elapsed_time.abs();
+    let mut forward = elapsed_time >= 0.0;
+
+    loop {
+        let target = if forward {
+            time + remaining
+        } else {
+            time - remaining
+        };
+        let crossed = if forward {
+            target >= duration
+        } else {
+            target <= 0.0
+        };
+        if !crossed {
+            *current_time = target;
+            advance.segments.push(TimeSegment::new(time, target));
+            return;
+        }
+
+        let (boundary, opposite) = if forward {
+            (duration, 0.0)
+        } else {
+            (0.0, duration)
+        };
+        remaining = (target - boundary).abs();
+        *completed_loops += 1;
+        advance.boundaries += 1;
+
+        let finished = match loop_mode {
+            LoopMode::Once => true,
+            LoopMode::Count(count) => *completed_loops >= count,
+            LoopMode::Loop | LoopMode::PingPong => false,
+        };
+        let ping_pong = loop_mode == LoopMode::PingPong;
+        // 倒放经过开头时，第0帧的事件属于这一段；往返播放时则交给反射后的正放区间
+        let mut segment = TimeSegment::new(time, boundary);
+        segment.inclusive_end = !forward && (!ping_pong || finished);
+        advance.segments.push(segment);
+
+        if finished || duration <= 0.0 {
+            *current_time = boundary;
+            advance.finished = true;
+            return;
+        }
+
+        if ping_pong {
+            // 在边界处反射
+            *reversed = !*reversed;
+            forward = !forward;
+            time = boundary;
+        } else {
+            advance.wrapped = true;
+            time = opposite;
+        }
+
+        // 一次推进跨过很多轮时（如从后台恢复），直接跳过中间完整的轮次，
+        // 只保留最后几轮的区间，计算量和区间数量与推进的时间无关
+        let full_loops = (remaining / duration) as u32;
+        if full_loops > MAX_FULL_LOOPS {
+            let mut skipped = full_loops - MAX_FULL_LOOPS;
+            if let LoopMode::Count(count) = loop_mode {
+                // 完成播放的那一轮仍然需要区间
+                skipped = skipped.min(count.saturating_sub(*completed_loops + 1));
+            }
+            if ping_pong {
+                // 跳过偶数轮，方向保持不变
+                skipped -= skipped % 2;
+            }
+            remaining -= skipped as f32 * duration;
+            *completed_loops += skipped;
+            advance.boundaries += skipped;
+            advance.skipped_loops += skipped;
+        }
     }
 }
 
-fn collect_current_time_active_shape(
-    instance_id: &str,
+/// 按当前时间重建子影片的播放进度，重建过的子影片标记为仍在显示列表中
+fn rebuild_clip_state(
+    parent_id: u64,
     timeline: &BTreeMap<u16, DepthTimeline>,
     current_time: f32,
-    elapsed_time: f32,
-    active_clip: &mut HashMap<String, MovieClip>,
+    active_clip: &mut HashMap<u64, ActiveClip>,
     children_clip: &HashMap<CharacterId, MovieClip>,
-    current_skins: &mut HashMap<String, String>,
-    frame_rate: f32,
-    active_instances: &mut Vec<RuntimeInstance>,
-    base_transform: Matrix,
-    base_color_transform: swf::ColorTransform,
-    base_blend_mode: BlendMode,
-    base_filters: Vec<RenderFilter>,
-) -> Result<()> {
+) {
     for (depth, depth_timeline) in timeline {
         let placements = &depth_timeline.placement;
-        let (Some(start_placement), _end_placement) = find_key_frame(current_time, placements)
-        else {
+        let (Some(start_placement), _) = find_key_frame(current_time, placements) else {
+            continue;
+        };
+        let Some(id) = placements[start_placement].resource_id() else {
+            continue;
+        };
+        let Some(child_clip) = children_clip.get(&id) else {
             continue;
         };
 
-        let start_keyframe = placements.get(start_placement).unwrap();
-        if let Some(id) = start_keyframe.resource_id() {
-            // 唯一标识
-            let instance_id = format!("{}_{}", instance_id, depth);
-
-            let transforms = &depth_timeline.transforms;
-            // 既然start存在那么transform一定存在
-            let (start, _end) = find_key_frame(current_time, transforms);
-            // TODO: 补帧出现变换异常
-            // let transform = match end_placement {
-            //     // 如果后一帧是空针，不需要插值计算，所在帧切换了资源图也不插帧
-            //     Some(index)
-            //         if placements.get(index).unwrap().resource_id().is_none()
-            //             || placements.get(index).unwrap().resource_id().unwrap() != id =>
-            //     {
-            //         transforms.get(start.unwrap()).unwrap().matrix
-            //     }
-            //     _ => lerp_transform(
-            //         start.expect("transform 必须存在、否则这会是一个Bug"),
-            //         end,
-            //         transforms,
-            //         current_time,
-            //     ),
-            // };
-            let transform: Matrix = transforms.get(start.unwrap()).unwrap().matrix;
-            let current_transform = base_transform * transform;
-
-            // 颜色变换
-            let color_transform = start_keyframe.color_transform().color_transform;
-            let current_color_transform = base_color_transform * color_transform;
-
-            if let Some(child_clip) = children_clip.get(&id) {
-                // 混合模式
-                let blend_mode = start_keyframe.blend_mode();
-
-                // 滤镜
-                let mut filters: Vec<RenderFilter> = start_keyframe
-                    .filters()
-                    .iter()
-                    .map(RenderFilter::from)
-                    .collect();
+        let placement_index = placement_start(placements, start_placement);
+        let instance_id = stable_instance_id(parent_id, *depth, id, placement_index);
+        let placement_start = placements[placement_index].time();
+        let rebuilt = ActiveClip::new(child_clip, placement_start, current_time);
+        let time = rebuilt.time;
+        if !child_clip.is_skin_frame() {
+            rebuild_clip_state(
+                instance_id,
+                child_clip.timeline(),
+                time,
+                active_clip,
+                children_clip,
+            );
+        }
+        let active = active_clip.entry(instance_id).or_insert(rebuilt);
+        active.time = time;
+        active.placement_start = placement_start;
+        active.alive = true;
+    }
+}
 
-                filters.append(&mut base_filters.clone());
+/// 当前实例开始放置的关键帧下标：向前找到最近的Place/Replace关键帧，
+/// 旧数据没有记录这个标记时，以连续放置同一资源的开始为准
+fn placement_start(placements: &[Placement], index: usize) -> usize {
+    let id = placements[index].resource_id();
+    let mut start = index;
+    while start > 0
+        && !placements[start].is_new_instance()
+        && placements[start - 1].resource_id() == id
+    {
+        start -= 1;
+    }
+    start
+}
 
-                let clip_instance_id = format!("{}_{}", instance_id, id);
-                let mut child_clip = if let Some(child_clip) = active_clip.remove(&clip_instance_id)
-                {
-                    child_clip
-                } else {
-                    child_clip.clone()
-                };
+/// 根时间轴的实例id，即FNV-1a的初始值
+const ROOT_INSTANCE_ID: u64 = 0xcbf2_9ce4_8422_2325;
 
-                // 判断是否是皮肤clip
-                let child_current_time = if child_clip.is_skin_frame() {
-                    // 是皮肤clip
-                    let name = child_clip.name().expect("替换皮肤的影片剪辑必须命名！");
-                    // 是否设置了皮肤
-                    let skip_frame = if let Some(skip_name) = current_skins.get(name) {
-                        if let Some(frame) = child_clip.skin_frame(skip_name) {
-                            *frame
-                        } else {
-                            return Err(RuntimeError::SkinNotFound(skip_name.to_owned()).into());
-                        }
-                    } else {
-                        // 没有设置皮肤，使用默认皮肤
-                        child_clip.default_skin_frame()
-                    };
-                    // 计算对应帧对应的事件
-                    skip_frame as f32 / frame_rate
-                } else {
-                    child_clip.current_time
-                };
+/// 由父实例id、深度、资源id和开始放置的关键帧下标组合出稳定的实例id（FNV-1a），与帧数和遍历顺序无关。
+///
+/// 同一深度上移除后再次放置的同一资源开始于不同的关键帧，得到新的id
+fn stable_instance_id(parent_id: u64, depth: Depth, id: CharacterId, placement: usize) -> u64 {
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+    depth
+        .to_le_bytes()
+        .into_iter()
+        .chain(id.to_le_bytes())
+        .chain((placement as u32).to_le_bytes())
+        .fold(parent_id, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+        })
+}
 
-                collect_current_time_active_shape(
-                    &instance_id,
-                    child_clip.timeline(),
-                    child_current_time,
-                    elapsed_time,
-                    active_clip,
-                    children_clip,
-                    current_skins,
-                    frame_rate,
-                    active_instances,
-                    current_transform,
-                    current_color_transform,
-                    blend_mode,
-                    filters,
-                )?;
-                child_clip.current_time += elapsed_time;
-                if child_clip.current_time >= child_clip.duration() {
-                    child_clip.current_time %= child_clip.duration();
-                }
-                active_clip.insert(clip_instance_id, child_clip);
-            } else {
-                // 记录这个child_movie找到的shape为当前活动实例，将每一帧的实例Shape扁平化输出，游戏引擎中迭代实在不方便
-                active_instances.push(RuntimeInstance::new(
-                    id,
-                    current_transform,
-                    current_color_transform,
-                    base_blend_mode,
-                    base_filters.clone(),
-                ));
-            }
-        }
-    }
-    Ok(())
+/// 时间转换为帧号，加上一个很小的值避免浮点误差导致少算一帧
+fn time_to_frame(time: f32, frame_rate: f32) -> u32 {
+    (time * frame_rate + FRAME_EPSILON).floor().max(0.0) as u32
 }
 
 fn find_key_frame<T: KeyFrame>(time: f32, key_frames: &[T]) -> (Option<usize>, Option<usize>) {
@@ -448,38 +2347,152 @@ fn find_key_frame<T: KeyFrame>(time: f32, key_frames: &[T]) -> (Option<usize>, O
     }
 }
 
+/// 实例所在的子影片
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstanceAncestor {
+    /// 子影片的稳定实例id
+    pub instance_id: u64,
+    /// 子影片在父时间轴上的深度
+    pub depth: Depth,
+    pub character_id: CharacterId,
+    /// 放置时设置的实例名
+    pub name: Option<String>,
+    /// 子影片的链接名
+    pub linkage_name: Option<String>,
+    /// 子影片自身的滤镜数量。实例的滤镜从内层到外层排列，
+    /// 每个祖先的滤镜依次排在其内层祖先的滤镜之后
+    pub filter_count: usize,
+    /// 子影片的混合模式
+    pub blend_mode: BlendMode,
+}
+
+impl InstanceAncestor {
+    /// 是否是指定实例名或链接名的子影片
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name.as_deref() == Some(name) || self.linkage_name.as_deref() == Some(name)
+    }
+
+    /// 带有滤镜或非Normal混合模式的子影片需要作为一个整体绘制到离屏纹理后再合成
+    pub fn is_offscreen(&self) -> bool {
+        self.filter_count > 0 || self.blend_mode != BlendMode::Normal
+    }
+}
+
 /// 实例只需要存储用于引擎渲染的Shape就行吗？
 /// 在多个Shape合成的MovieClip上应用滤镜，需要一起渲染，
-#[derive(Debug, Default)]
+#[derive(Debug, Default, PartialEq)]
 pub struct RuntimeInstance {
     id: CharacterId,
+    /// 稳定的实例id，实例在显示列表中存在期间保持不变
+    instance_id: u64,
+    /// 在父时间轴上的深度
+    depth: Depth,
+    /// 从根到直接父级的子影片，多个实例共享同一份
+    ancestors: Arc<[InstanceAncestor]>,
+    /// 绘制顺序，越小越先绘制
+    draw_order: u32,
     transform: Matrix,
     color_transform: swf::ColorTransform,
     blend: BlendMode,
     filters: Vec<RenderFilter>,
+    /// 不为空时这个实例是遮罩的一部分，不应该直接绘制
+    mask_layer: Option<u64>,
+    /// 遮住这个实例的遮罩层，实例只在所有遮罩层内可见
+    masked_by: Arc<[u64]>,
 }
 
-impl RuntimeInstance {
-    fn new(
-        id: CharacterId,
-        transform: Matrix,
-        color_transform: swf::ColorTransform,
-        blend: BlendMode,
-        filters: Vec<RenderFilter>,
-    ) -> Self {
+impl Clone for RuntimeInstance {
+    fn clone(&self) -> Self {
         Self {
-            id,
-            transform,
-            color_transform,
-            blend,
-            filters,
+            id: self.id,
+            instance_id: self.instance_id,
+            depth: self.depth,
+            ancestors: self.ancestors.clone(),
+            draw_order: self.draw_order,
+            transform: self.transform,
+            color_transform: self.color_transform,
+            blend: self.blend,
+            filters: self.filters.clone(),
+            mask_layer: self.mask_layer,
+            masked_by: self.masked_by.clone(),
         }
     }
 
+    /// 复用滤镜列表的空间，播放器保存输出的副本时不需要重新分配
+    fn clone_from(&mut self, source: &Self) {
+        self.id = source.id;
+        self.instance_id = source.instance_id;
+        self.depth = source.depth;
+        self.ancestors.clone_from(&source.ancestors);
+        self.draw_order = source.draw_order;
+        self.transform = source.transform;
+        self.color_transform = source.color_transform;
+        self.blend = source.blend;
+        self.filters.clone_from(&source.filters);
+        self.mask_layer = source.mask_layer;
+        self.masked_by.clone_from(&source.masked_by);
+    }
+}
+
+impl RuntimeInstance {
     pub fn id(&self) -> CharacterId {
         self.id
     }
 
+    pub fn instance_id(&self) -> u64 {
+        self.instance_id
+    }
+
+    /// 全局绘制顺序，越小越先绘制
+    pub fn draw_order(&self) -> u32 {
+        self.draw_order
+    }
+
+    pub fn depth(&self) -> Depth {
+        self.depth
+    }
+
+    /// 从根时间轴到这个实例的深度路径
+    pub fn depth_path(&self) -> Vec<Depth> {
+        self.ancestors
+            .iter()
+            .map(|ancestor| ancestor.depth)
+            .chain(std::iter::once(self.depth))
+            .collect()
+    }
+
+    /// 从根到直接父级的子影片，根时间轴上的实例为空
+    pub fn ancestors(&self) -> &[InstanceAncestor] {
+        &self.ancestors
+    }
+
+    /// 直接父级子影片
+    pub fn parent(&self) -> Option<&InstanceAncestor> {
+        self.ancestors.last()
+    }
+
+    /// 实例路径，与[`AnimationPlayer::clip_mut`]使用的路径一致，如`body/arm`
+    pub fn instance_path(&self) -> String {
+        self.ancestors
+            .iter()
+            .map(|ancestor| {
+                ancestor
+                    .name
+                    .clone()
+                    .or_else(|| ancestor.linkage_name.clone())
+                    .unwrap_or_else(|| ancestor.depth.to_string())
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// 是否位于指定实例名或链接名的子影片中
+    pub fn has_ancestor(&self, name: &str) -> bool {
+        self.ancestors
+            .iter()
+            .any(|ancestor| ancestor.is_named(name))
+    }
+
     pub fn blend(&self) -> BlendMode {
         self.blend
     }
@@ -496,9 +2509,38 @@ impl RuntimeInstance {
         self.color_transform
     }
 
+    /// 实例所在的遮罩层id（遮罩实例的稳定id），为`None`表示普通实例
+    pub fn mask_layer(&self) -> Option<u64> {
+        self.mask_layer
+    }
+
+    pub fn is_mask(&self) -> bool {
+        self.mask_layer.is_some()
+    }
+
+    /// 遮住这个实例的遮罩层id，由外到内排列
+    pub fn masked_by(&self) -> &[u64] {
+        &self.masked_by
+    }
+
     pub fn filters_mut(&mut self) -> &mut Vec<RenderFilter> {
         &mut self.filters
     }
+
+    /// 第`index`个祖先自身的滤镜，即继承的滤镜中属于这个子影片的部分
+    pub fn ancestor_filters(&self, index: usize) -> &[RenderFilter] {
+        let Some(ancestor) = self.ancestors.get(index) else {
+            return &[];
+        };
+        // 内层祖先的滤镜排在前面
+        let offset: usize = self.ancestors[index + 1..]
+            .iter()
+            .map(|ancestor| ancestor.filter_count)
+            .sum();
+        self.filters
+            .get(offset..offset + ancestor.filter_count)
+            .unwrap_or_default()
+    }
 }
 
 fn lerp_transform(
@@ -557,3 +2599,1027 @@ fn calc_lerp_factor(start_time: f32, end_time: f32, current_time: f32) -> f32 {
 
     raw_t.clamp(0.0, 1.0)
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use serde_json::{Value, json};
+
+    use crate::parser::{
+        Animations,
+        parse_shape::tessellator::{Draw, DrawType, Mesh, Vertex},
+    };
+
+    use super::*;
+
+    pub(super) fn placement(time: f32, resource_id: Option<CharacterId>) -> Value {
+        json!({
+            "time": time,
+            "resource_id": resource_id,
+            "blend_mode": "Normal",
+            "color_transform": { "mult_color": [1.0, 1.0, 1.0, 1.0], "add_color": [0, 0, 0, 0] },
+            "filters": [],
+        })
+    }
+
+    pub(super) fn transform(time: f32) -> Value {
+        json!({ "time": time, "matrix": { "a": 1.0, "b": 0.0, "c": 0.0, "d": 1.0 } })
+    }
+
+    /// 10帧/秒，根动画10帧：
+    /// - 深度1：形状1常驻
+    /// - 深度2：第2帧放置子影片10，第6帧移除
+    /// - 子影片10共4帧：形状2，第2帧替换为形状3
+    pub(super) fn test_animations() -> Animations {
+        serde_json::from_value(json!({
+            "meta": { "frame_rate": 10.0, "frames": 10, "version": "test" },
+            "children_clip": {
+                "10": {
+                    "name": "arm",
+                    "id": 10,
+                    "duration": 0.4,
+                    "timeline": {
+                        "1": {
+                            "placement": [placement(0.0, Some(2)), placement(0.2, Some(3))],
+                            "transforms": [transform(0.0), transform(0.2)],
+                        }
+                    },
+                    "skin_frames": {},
+                    "default_skin": "",
+                    "events": [{ "time": 0.1, "name": "swing" }],
+                }
+            },
+            "animations": {
+                "default": {
+                    "name": "default",
+                    "duration": 1.0,
+                    "timeline": {
+                        "1": {
+                            "placement": [placement(0.0, Some(1))],
+                            "transforms": [transform(0.0)],
+                        },
+                        "2": {
+                            "placement": [placement(0.2, Some(10)), placement(0.6, None)],
+                            "transforms": [transform(0.2)],
+                        }
+                    },
+                    "events": [{ "time": 0.5, "name": "hit", "payload": "10" }],
+                    "labels": [{ "time": 0.5, "name": "mid" }],
+                }
+            },
+        }))
+        .unwrap()
+    }
+
+    pub(super) fn test_player() -> AnimationPlayer {
+        let animations = test_animations();
+        let mut player = AnimationPlayer::new(
+            animations.animations,
+            animations.children_clip,
+            animations.meta.frame_rate,
+        );
+        player.set_play_animation("default", true, None).unwrap();
+        player
+    }
+
+    pub(super) fn ids(instances: &[RuntimeInstance]) -> Vec<CharacterId> {
+        instances.iter().map(RuntimeInstance::id).collect()
+    }
+
+    /// 从注册点开始、边长为`size`像素的白色正方形网格
+    pub(super) fn square_mesh(size: f32) -> Mesh {
+        Mesh {
+            draws: vec![Draw {
+                draw_type: DrawType::Color,
+                vertices: [(0.0, 0.0), (size, 0.0), (size, size), (0.0, size)]
+                    .into_iter()
+                    .map(|(x, y)| Vertex {
+                        x,
+                        y,
+                        color: swf::Color::WHITE,
+                    })
+                    .collect(),
+                indices: vec![0, 1, 2, 0, 2, 3],
+                mask_index_count: 6,
+            }],
+            gradients: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn seek_rebuilds_child_clip_time() -> Result<()> {
+        let mut player = test_player();
+        let mut instances = Vec::new();
+
+        player.goto_label("mid")?;
+        assert_eq!(player.current_frame(), 5);
+        player.set_playing(false);
+        player.update(&mut instances, 0.1);
+        // 子影片在第2帧放置，跳转到第5帧时应处于其第3帧
+        assert_eq!(ids(&instances), vec![1, 3]);
+
+        player.step_frames(-4);
+        assert_eq!(player.current_frame(), 1);
+        player.update(&mut instances, 0.1);
+        assert_eq!(ids(&instances), vec![1]);
+
+        assert!(player.goto_frame(10).is_err());
+        assert!(player.goto_label("missing").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn loop_modes_fire_events_at_boundaries() -> Result<()> {
+        let mut player = test_player();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        player.register_frame_event("default", "hit".to_owned(), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        })?;
+        let completed = Arc::new(AtomicUsize::new(0));
+        let on_completion = completed.clone();
+        player.set_play_animation(
+            "default",
+            LoopMode::Count(2),
+            Some(Box::new(move || {
+                on_completion.fetch_add(1, Ordering::SeqCst);
+            })),
+        )?;
+
+        let mut instances = Vec::new();
+        // 第一轮末尾跨过边界进入第二轮
+        player.seek(0.95);
+        player.update(&mut instances, 0.1);
+        assert_eq!(player.completed_loops(), 1);
+        assert!(player.is_playing());
+        player.seek(0.45);
+        player.update(&mut instances, 0.1);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        player.update(&mut instances, 1.0);
+        assert!(!player.is_playing());
+        assert_eq!(completed.load(Ordering::SeqCst), 1);
+
+        // 倒放同样会触发经过的事件
+        player.set_speed(-1.0);
+        player.set_play_animation("default", LoopMode::PingPong, None)?;
+        player.set_playing(true);
+        player.seek(0.55);
+        player.update(&mut instances, 0.1);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        // 到达开头后反向
+        player.seek(0.05);
+        player.update(&mut instances, 0.1);
+        assert!((player.current_time() - 0.05).abs() < 1.0e-4);
+        player.update(&mut instances, 0.5);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn large_delta_fires_every_loop() -> Result<()> {
+        let mut player = test_player();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        player.register_frame_event("default", "hit".to_owned(), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        })?;
+
+        let mut instances = Vec::new();
+        player.seek(0.7);
+        // 跨过三次循环边界，停在第三轮的0.6秒处
+        player.update(&mut instances, 2.9);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        assert_eq!(player.completed_loops(), 3);
+        assert_eq!(player.current_frame(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn huge_delta_skips_whole_loops() -> Result<()> {
+        let mut player = test_player();
+        player.set_event_queue_enabled(true);
+        player.seek(0.7);
+        player.drain_events();
+
+        // 一次推进一百万轮，只逐轮处理最后几轮
+        let mut instances = Vec::new();
+        player.update(&mut instances, 1_000_000.0);
+        assert!(player.time_advance.segments.len() <= MAX_FULL_LOOPS as usize + 2);
+        assert_eq!(player.completed_loops(), 1_000_000);
+        assert!((player.current_time() - 0.7).abs() < 0.05);
+        let loops: Vec<_> = player
+            .drain_events()
+            .into_iter()
+            .filter_map(|event| match event {
+                PlayerEvent::Looped { loop_count, .. } => Some(loop_count),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(loops, [1, 999_998, 999_999, 1_000_000]);
+
+        // 有限次数的循环仍然停在最后一轮的末尾
+        player.set_play_animation("default", LoopMode::Count(5), None)?;
+        player.update(&mut instances, 1_000_000.0);
+        assert!(!player.is_playing());
+        assert_eq!(player.completed_loops(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn frame_events_must_be_reachable() -> Result<()> {
+        let mut animations = test_animations();
+        // 子影片11只嵌套放置子影片10
+        let body: MovieClip = serde_json::from_value(json!({
+            "name": "body",
+            "id": 11,
+            "duration": 0.4,
+            "timeline": {
+                "1": { "placement": [placement(0.0, Some(10))], "transforms": [transform(0.0)] }
+            },
+            "skin_frames": {},
+            "default_skin": "",
+            "events": [],
+        }))?;
+        animations.children_clip.insert(11, body);
+        let mut nested = animations.animations["default"].clone();
+        nested.name = "nested".to_owned();
+        nested.timeline.get_mut(&2).unwrap().placement[0] =
+            serde_json::from_value(placement(0.2, Some(11)))?;
+        let mut empty = animations.animations["default"].clone();
+        empty.name = "empty".to_owned();
+        empty.timeline.remove(&2);
+        animations.animations.insert(nested.name.clone(), nested);
+        animations.animations.insert(empty.name.clone(), empty);
+        let mut player = AnimationPlayer::with_library(Arc::new(animations.into()));
+
+        // 嵌套的子影片上的事件可以监听，没有放置的子影片上的事件不行
+        player.register_frame_event("nested", "swing".to_owned(), || {})?;
+        assert!(
+            player
+                .register_frame_event("empty", "swing".to_owned(), || {})
+                .is_err()
+        );
+        player.register_frame_event("empty", "hit".to_owned(), || {})?;
+        Ok(())
+    }
+
+    #[test]
+    fn event_queue_and_listener_handles() -> Result<()> {
+        let mut player = test_player();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let listener = player.register_frame_event("default", "hit".to_owned(), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        })?;
+        // 子影片上的事件也可以监听
+        let swings = Arc::new(AtomicUsize::new(0));
+        let counter = swings.clone();
+        player.register_frame_event("default", "swing".to_owned(), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        })?;
+        assert!(
+            player
+                .register_frame_event("default", "missing".to_owned(), || {})
+                .is_err()
+        );
+        player.set_event_queue_enabled(true);
+        player.set_play_animation("default", LoopMode::Count(2), None)?;
+
+        let mut instances = Vec::new();
+        for _ in 0..20 {
+            player.update(&mut instances, 0.1);
+        }
+        let events = player.drain_events();
+        let hit = PlayerEvent::FrameEvent {
+            animation: "default".to_owned(),
+            name: "hit".to_owned(),
+            payload: Some("10".to_owned()),
+            instance_path: String::new(),
+        };
+        let swing = PlayerEvent::FrameEvent {
+            animation: "default".to_owned(),
+            name: "swing".to_owned(),
+            payload: None,
+            instance_path: "arm".to_owned(),
+        };
+        let label = PlayerEvent::LabelEntered {
+            animation: "default".to_owned(),
+            label: "mid".to_owned(),
+        };
+        let round = [swing, label, hit];
+        let mut expected = vec![PlayerEvent::Started {
+            animation: "default".to_owned(),
+        }];
+        expected.extend(round.iter().cloned());
+        expected.push(PlayerEvent::Looped {
+            animation: "default".to_owned(),
+            loop_count: 1,
+        });
+        expected.extend(round.iter().cloned());
+        expected.push(PlayerEvent::Completed {
+            animation: "default".to_owned(),
+        });
+        assert_eq!(events, expected);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        assert_eq!(swings.load(Ordering::SeqCst), 2);
+
+        assert!(player.remove_frame_event_listener(listener));
+        assert!(!player.remove_frame_event_listener(listener));
+        Ok(())
+    }
+
+    #[test]
+    fn child_and_root_events_are_time_ordered() -> Result<()> {
+        let mut player = test_player();
+        let mut instances = Vec::new();
+        player.set_event_queue_enabled(true);
+        player.seek(0.4);
+        // 子影片从最后一帧开始，回绕后才经过"swing"，晚于根时间轴0.5秒的标签和事件
+        player.clip_mut("arm")?.goto_and_play(3);
+        player.drain_events();
+        player.update(&mut instances, 0.25);
+
+        let names: Vec<_> = player
+            .drain_events()
+            .into_iter()
+            .map(|event| match event {
+                PlayerEvent::FrameEvent { name, .. } => name,
+                PlayerEvent::LabelEntered { label, .. } => label,
+                event => panic!("unexpected event {event:?}"),
+            })
+            .collect();
+        assert_eq!(names, ["mid", "hit", "swing"]);
+        Ok(())
+    }
+
+    #[test]
+    fn nested_clip_control() -> Result<()> {
+        let mut player = test_player();
+        let mut instances = Vec::new();
+        assert!(player.clip_mut("missing").is_err());
+
+        player.clip_mut("arm")?.goto_and_stop(2);
+        player.seek(0.2);
+        for _ in 0..4 {
+            player.update(&mut instances, 0.1);
+            assert_eq!(ids(&instances), vec![1, 3]);
+        }
+
+        player.clip_mut("arm")?.goto_and_play(0);
+        player.seek(0.2);
+        player.update(&mut instances, 0.1);
+        assert_eq!(ids(&instances), vec![1, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn clip_control_applies_to_every_named_instance() -> Result<()> {
+        // 深度3从第0帧起放置另一个"arm"，与深度2的实例相差2帧
+        let mut animations = test_animations();
+        let timeline = &mut animations.animations.get_mut("default").unwrap().timeline;
+        timeline.insert(
+            3,
+            serde_json::from_value(json!({
+                "placement": [placement(0.0, Some(10))],
+                "transforms": [transform(0.0)],
+            }))?,
+        );
+        let mut player = AnimationPlayer::with_library(Arc::new(animations.into()));
+        player.set_play_animation("default", true, None)?;
+        let mut instances = Vec::new();
+
+        // 停止时各自停在自己的帧：深度2处于第1帧，深度3处于第3帧
+        player.seek(0.3);
+        player.clip_mut("arm")?.stop();
+        for _ in 0..2 {
+            player.update(&mut instances, 0.1);
+            assert_eq!(ids(&instances), vec![1, 2, 3]);
+        }
+
+        // 跳转作用于所有同名实例，深度2在第6帧移除，回到第3帧继续检查
+        player.seek(0.3);
+        player.clip_mut("arm")?.goto_and_stop(0);
+        for _ in 0..2 {
+            player.update(&mut instances, 0.1);
+            assert_eq!(ids(&instances), vec![1, 2, 2]);
+        }
+        player.seek(0.3);
+        player.clip_mut("arm")?.goto_and_stop(2);
+        player.update(&mut instances, 0.1);
+        assert_eq!(ids(&instances), vec![1, 3, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn child_clip_lifecycle_follows_placement() -> Result<()> {
+        let mut player = test_player();
+        let mut instances = Vec::new();
+        player.set_playing(true);
+        player.seek(0.2);
+        player.update(&mut instances, 0.3);
+        assert_eq!(player.active_clip.len(), 1);
+        // 第6帧移除后不再保留子影片状态
+        player.update(&mut instances, 0.2);
+        player.update(&mut instances, 0.1);
+        assert!(player.active_clip.is_empty());
+
+        // 同一资源被重新放置时是新的实例
+        let mut placements: Vec<Placement> =
+            serde_json::from_value(json!([placement(0.0, Some(10)), placement(0.3, Some(10))]))?;
+        assert_eq!(placement_start(&placements, 1), 0);
+        let mut replaced = placement(0.3, Some(10));
+        replaced["new_instance"] = json!(true);
+        placements = serde_json::from_value(json!([placement(0.0, Some(10)), replaced]))?;
+        assert_eq!(placement_start(&placements, 1), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn replaced_instance_gets_new_id() -> Result<()> {
+        // 子影片在第6帧移除，第8帧再次放置
+        let mut animations = test_animations();
+        let timeline = &mut animations.animations.get_mut("default").unwrap().timeline;
+        let depth = timeline.get_mut(&2).unwrap();
+        depth
+            .placement
+            .push(serde_json::from_value(placement(0.8, Some(10)))?);
+        depth
+            .transforms
+            .push(serde_json::from_value(transform(0.8))?);
+        let mut player = AnimationPlayer::with_library(Arc::new(animations.into()));
+        player.set_play_animation("default", true, None)?;
+        player.set_instance_diff_enabled(true);
+        let mut instances = Vec::new();
+
+        player.seek(0.3);
+        player.update(&mut instances, 0.1);
+        let first = instances[1].instance_id();
+        let first_arm = instances[1].parent().unwrap().instance_id;
+        player.seek(0.8);
+        player.update(&mut instances, 0.1);
+        assert_eq!(ids(&instances), vec![1, 2]);
+        assert_ne!(instances[1].instance_id(), first);
+        assert_ne!(instances[1].parent().unwrap().instance_id, first_arm);
+        let diff = player.instance_diff().unwrap();
+        assert_eq!(diff.despawned, vec![first]);
+        assert_eq!(diff.spawned, vec![1]);
+        Ok(())
+    }
+
+    #[test]
+    fn players_share_library() -> Result<()> {
+        let library = Arc::new(AnimationLibrary::from(test_animations()));
+        let mut first = AnimationPlayer::with_library(library.clone());
+        let mut second = AnimationPlayer::with_library(library.clone());
+        assert_eq!(Arc::strong_count(&library), 3);
+        first.set_play_animation("default", true, None)?;
+        second.set_play_animation("default", true, None)?;
+
+        let mut first_instances = Vec::new();
+        let mut second_instances = Vec::new();
+        first.seek(0.2);
+        second.seek(0.2);
+        first.clip_mut("arm")?.goto_and_stop(2);
+        first.update(&mut first_instances, 0.1);
+        second.update(&mut second_instances, 0.1);
+        // 各自的子影片状态互不影响
+        assert_eq!(ids(&first_instances), vec![1, 3]);
+        assert_eq!(ids(&second_instances), vec![1, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn stable_instance_ids_and_diff() {
+        let mut player = test_player();
+        player.set_instance_diff_enabled(true);
+        let mut instances = Vec::new();
+
+        player.update(&mut instances, 0.1);
+        let shape_id = instances[0].instance_id();
+        let diff = player.instance_diff().unwrap();
+        assert_eq!(diff.spawned, vec![0]);
+
+        // 第2帧放置子影片
+        player.update(&mut instances, 0.1);
+        player.update(&mut instances, 0.1);
+        assert_eq!(instances[0].instance_id(), shape_id);
+        assert_eq!(instances[1].draw_order(), 1);
+        let arm_id = instances[1].instance_id();
+        let diff = player.instance_diff().unwrap();
+        assert_eq!(
+            (diff.spawned.clone(), diff.updated.clone()),
+            (vec![1], vec![])
+        );
+
+        // 子影片第2帧替换形状，第6帧移除子影片
+        player.update(&mut instances, 0.1);
+        player.update(&mut instances, 0.1);
+        let diff = player.instance_diff().unwrap();
+        assert_eq!(diff.despawned, vec![arm_id]);
+        assert_eq!(diff.spawned, vec![1]);
+        let swapped_id = instances[1].instance_id();
+        assert_ne!(swapped_id, arm_id);
+        player.update(&mut instances, 0.2);
+        player.update(&mut instances, 0.1);
+        let diff = player.instance_diff().unwrap();
+        assert_eq!(diff.despawned, vec![swapped_id]);
+        assert!(diff.spawned.is_empty() && diff.updated.is_empty());
+
+        player.set_instance_diff_enabled(false);
+        assert!(player.instance_diff().is_none());
+    }
+
+    #[test]
+    fn instance_hierarchy() {
+        let mut player = test_player();
+        let mut instances = Vec::new();
+        player.seek(0.2);
+        player.update(&mut instances, 0.1);
+
+        assert!(instances[0].ancestors().is_empty());
+        assert_eq!(instances[0].depth_path(), vec![1]);
+        let arm = &instances[1];
+        assert_eq!(arm.depth_path(), vec![2, 1]);
+        assert_eq!(arm.instance_path(), "arm");
+        assert!(arm.has_ancestor("arm"));
+        let parent = arm.parent().unwrap();
+        assert_eq!((parent.character_id, parent.depth), (10, 2));
+        assert_eq!(parent.linkage_name.as_deref(), Some("arm"));
+        assert_eq!(parent.name, None);
+    }
+
+    #[test]
+    fn attachment_transform() {
+        let mut animations = test_animations();
+        let timeline = &mut animations.animations.get_mut("default").unwrap().timeline;
+        let arm = &mut timeline.get_mut(&2).unwrap().transforms[0].matrix;
+        arm.a = 2.0;
+        arm.tx = Twips::from_pixels(5.0);
+        let mut player = AnimationPlayer::with_library(Arc::new(animations.into()));
+        player.set_play_animation("default", true, None).unwrap();
+
+        assert_eq!(player.instance_transform("arm"), None);
+        player.seek(0.3);
+        let transform = player.instance_transform("arm").unwrap();
+        assert_eq!((transform.a, transform.tx), (2.0, Twips::from_pixels(5.0)));
+        assert_eq!(
+            player.instance_transform_mat4("arm"),
+            Some(Mat4::from(transform))
+        );
+        assert_eq!(player.instance_transform("missing"), None);
+    }
+
+    #[test]
+    fn attachment_transform_matches_output() -> Result<()> {
+        // 子影片在第3帧移动，并加入另一个可以替换它的子影片
+        let mut animations = test_animations();
+        let leg = json!({
+            "name": "leg",
+            "id": 11,
+            "duration": 0.4,
+            "timeline": {
+                "1": {
+                    "placement": [placement(0.0, Some(2))],
+                    "transforms": [transform(0.0)],
+                }
+            },
+            "skin_frames": {},
+            "default_skin": "",
+        });
+        animations
+            .children_clip
+            .insert(11, serde_json::from_value(leg)?);
+        let mut moved: Transform = serde_json::from_value(transform(0.3))?;
+        moved.matrix.tx = Twips::new(200);
+        let timeline = &mut animations.animations.get_mut("default").unwrap().timeline;
+        timeline.get_mut(&2).unwrap().transforms.push(moved);
+        let mut player = AnimationPlayer::with_library(Arc::new(animations.into()));
+        player.set_play_animation("default", true, None)?;
+        let mut instances = Vec::new();
+
+        // 输出第2帧的画面后播放头已经在第3帧，查询结果与输出一致
+        player.seek(0.2);
+        player.update(&mut instances, 0.1);
+        let arm = player.instance_transform("arm").unwrap();
+        assert_eq!(arm, instances[1].transform_matrix());
+        assert_eq!(arm.tx, Twips::ZERO);
+        player.update(&mut instances, 0.1);
+        assert_eq!(
+            player.instance_transform("arm"),
+            Some(instances[1].transform_matrix())
+        );
+        assert_eq!(instances[1].transform_matrix().tx, Twips::new(200));
+
+        // 替换资源后按替换后的子影片查找
+        player.replace_instance_character("arm", 11)?;
+        player.update(&mut instances, 0.1);
+        assert!(instances[1].has_ancestor("leg"));
+        assert_eq!(
+            player.instance_transform("leg"),
+            Some(instances[1].transform_matrix())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn instance_overrides() -> Result<()> {
+        let mut player = test_player();
+        let mut instances = Vec::new();
+        player.seek(0.2);
+
+        player.set_instance_visible("arm", false)?;
+        player.update(&mut instances, 0.1);
+        assert_eq!(ids(&instances), vec![1]);
+        // 隐藏期间子影片继续播放
+        player.set_instance_visible("arm", true)?;
+        player.update(&mut instances, 0.1);
+        assert_eq!(ids(&instances), vec![1, 2]);
+        player.update(&mut instances, 0.1);
+        assert_eq!(ids(&instances), vec![1, 3]);
+
+        let flash = swf::ColorTransform {
+            r_add: 255,
+            ..Default::default()
+        };
+        player.set_instance_color_transform("arm", flash)?;
+        player.replace_instance_character("arm/1", 99)?;
+        player.update(&mut instances, 0.0);
+        assert_eq!(ids(&instances), vec![1, 99]);
+        assert_eq!(instances[0].color_transform().r_add, 0);
+        assert_eq!(instances[1].color_transform().r_add, 255);
+
+        player.clear_instance_overrides();
+        player.replace_instance_symbol("1", "arm")?;
+        player.update(&mut instances, 0.0);
+        assert_eq!(ids(&instances), vec![2, 3]);
+
+        assert!(player.replace_instance_symbol("1", "missing").is_err());
+        assert!(player.set_instance_visible("missing", false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn skin_sets_and_catalog() -> Result<()> {
+        let mut animations = test_animations();
+        animations.children_clip.insert(
+            20,
+            serde_json::from_value(json!({
+                "name": "hand",
+                "id": 20,
+                "duration": 0.2,
+                "timeline": {
+                    "1": {
+                        "placement": [placement(0.0, Some(4)), placement(0.1, Some(5))],
+                        "transforms": [transform(0.0), transform(0.1)],
+                    }
+                },
+                "skin_frames": { "bare": 0, "knight": 1 },
+                "default_skin": "bare",
+                "outfits": ["knight"],
+            }))?,
+        );
+        let timeline = &mut animations.animations.get_mut("default").unwrap().timeline;
+        for (depth, name) in [(3, "left_hand"), (4, "right_hand")] {
+            let mut hand = placement(0.0, Some(20));
+            hand["name"] = json!(name);
+            timeline.insert(
+                depth,
+                serde_json::from_value(
+                    json!({ "placement": [hand], "transforms": [transform(0.0)] }),
+                )?,
+            );
+        }
+        let mut library = AnimationLibrary::from(animations);
+        library.add_skin_set(
+            "mixed",
+            [
+                ("left_hand", "knight"),
+                ("right_hand", "missing"),
+                ("tail", "fur"),
+            ],
+        );
+        let mut player = AnimationPlayer::with_library(Arc::new(library));
+        player.set_play_animation("default", true, None)?;
+        player.set_playing(false);
+
+        let catalog = player.skin_catalog();
+        assert_eq!(
+            catalog.parts().collect::<Vec<_>>(),
+            vec!["hand", "left_hand", "right_hand"]
+        );
+        assert_eq!(catalog.skins("left_hand").unwrap(), ["bare", "knight"]);
+        assert_eq!(
+            catalog.skin_sets().collect::<Vec<_>>(),
+            vec!["knight", "mixed"]
+        );
+
+        let mut instances = Vec::new();
+        let mut render = |player: &mut AnimationPlayer| {
+            player.seek(0.0);
+            player.update(&mut instances, 0.0);
+            ids(&instances)
+        };
+        // 同一子影片的两个实例可以分别换肤
+        player.set_skin("left_hand", "knight")?;
+        assert_eq!(render(&mut player), vec![1, 5, 4]);
+        assert!(player.set_skin("hand", "missing").is_err());
+        assert!(
+            player
+                .set_skins([("right_hand", "knight"), ("tail", "fur")])
+                .is_err()
+        );
+        assert_eq!(render(&mut player), vec![1, 5, 4]);
+
+        player.apply_skin_set("knight")?;
+        assert_eq!(render(&mut player), vec![1, 5, 5]);
+        player.reset_skins();
+        assert_eq!(render(&mut player), vec![1, 4, 4]);
+        // 没有的皮肤回退为默认皮肤，不存在的部位被忽略
+        player.set_skin("right_hand", "knight")?;
+        player.apply_skin_set("mixed")?;
+        assert_eq!(render(&mut player), vec![1, 5, 4]);
+        assert!(player.apply_skin_set("missing").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn bounds_follow_output() -> Result<()> {
+        let rect = |min: f64, max: f64| Rectangle {
+            x_min: Twips::from_pixels(min),
+            x_max: Twips::from_pixels(max),
+            y_min: Twips::from_pixels(min),
+            y_max: Twips::from_pixels(max),
+        };
+        let mut animations = test_animations();
+        let arm = animations
+            .animations
+            .get_mut("default")
+            .unwrap()
+            .timeline
+            .get_mut(&2)
+            .unwrap();
+        arm.transforms[0].matrix.tx = Twips::from_pixels(100.0);
+        let mut blurred = placement(0.2, Some(10));
+        blurred["filters"] =
+            json!([{ "BlurFilter": { "blur_x": 4.0, "blur_y": 4.0, "flags": 8 } }]);
+        arm.placement[0] = serde_json::from_value(blurred)?;
+
+        let mut library = AnimationLibrary::from(animations);
+        library.set_shape_bounds([
+            (1, rect(0.0, 10.0)),
+            (2, rect(0.0, 20.0)),
+            (3, rect(-5.0, 5.0)),
+        ]);
+        let max_bounds = library.animation("default").unwrap().max_bounds().unwrap();
+        assert_eq!(max_bounds.x_min, Twips::ZERO);
+        // 子影片上的模糊扩展了包围盒
+        assert!(max_bounds.x_max > Twips::from_pixels(120.0));
+        assert!(max_bounds.y_min < Twips::from_pixels(-5.0));
+
+        let mut player = AnimationPlayer::with_library(Arc::new(library));
+        player.set_play_animation("default", true, None)?;
+        assert_eq!(player.bounds(), None);
+        let mut instances = Vec::new();
+        player.update(&mut instances, 0.1);
+        assert_eq!(player.bounds(), Some(rect(0.0, 10.0)));
+        assert_eq!(player.bounds_with_filters(), Some(rect(0.0, 10.0)));
+
+        player.seek(0.2);
+        player.update(&mut instances, 0.1);
+        let bounds = player.bounds().unwrap();
+        assert_eq!(
+            (bounds.x_min, bounds.x_max),
+            (Twips::ZERO, Twips::from_pixels(120.0))
+        );
+        let filter_bounds = player.bounds_with_filters().unwrap();
+        assert!(filter_bounds.x_max > bounds.x_max);
+        assert_eq!(filter_bounds.x_min, Twips::ZERO);
+        Ok(())
+    }
+
+    #[test]
+    fn hit_test_respects_draw_order_and_masks() -> Result<()> {
+        let meshes = [
+            (1, square_mesh(10.0)),
+            (2, square_mesh(20.0)),
+            (3, square_mesh(20.0)),
+        ];
+        let player_with = |animations: Animations| -> Result<AnimationPlayer> {
+            let mut library = AnimationLibrary::from(animations);
+            library.set_shape_meshes(meshes.iter().map(|(id, mesh)| (*id, mesh)));
+            let mut player = AnimationPlayer::with_library(Arc::new(library));
+            player.set_play_animation("default", true, None)?;
+            let mut instances = Vec::new();
+            player.seek(0.2);
+            player.update(&mut instances, 0.1);
+            Ok(player)
+        };
+
+        let player = player_with(test_animations())?;
+        let hit = player.hit_test(Vec2::new(5.0, 5.0)).unwrap();
+        assert_eq!((hit.character_id, hit.instance_path.as_str()), (2, "arm"));
+        assert_eq!(hit.draw_order, 1);
+        assert!(player.hit_test(Vec2::new(15.0, 15.0)).is_some());
+        assert!(player.hit_test(Vec2::new(30.0, 5.0)).is_none());
+
+        // 深度1的形状作为遮罩遮住深度2
+        let mut animations = test_animations();
+        let mut mask = placement(0.0, Some(1));
+        mask["clip_depth"] = json!(2);
+        let timeline = &mut animations.animations.get_mut("default").unwrap().timeline;
+        timeline.get_mut(&1).unwrap().placement[0] = serde_json::from_value(mask)?;
+        let player = player_with(animations)?;
+        let instances = player.active_instances();
+        assert!(instances[0].is_mask());
+        assert_eq!(instances[1].masked_by(), [instances[0].instance_id()]);
+        assert_eq!(
+            player.hit_test(Vec2::new(5.0, 5.0)).unwrap().character_id,
+            2
+        );
+        assert!(player.hit_test(Vec2::new(15.0, 15.0)).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn seek_skips_events_unless_asked() -> Result<()> {
+        let mut player = test_player();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        player.register_frame_event("default", "hit".to_owned(), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        })?;
+
+        player.goto_frame(8)?;
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+
+        player.set_seek_fires_events(true);
+        player.goto_frame(0)?;
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn root_motion_is_stripped_and_accumulated() {
+        let mut animations = test_animations();
+        let timeline = &mut animations.animations.get_mut("default").unwrap().timeline;
+        let body = &mut timeline.get_mut(&1).unwrap().transforms;
+        let mut moved = body[0].clone();
+        moved.time = 0.5;
+        moved.matrix.tx = Twips::from_pixels(10.0);
+        body.push(moved);
+        let mut player = AnimationPlayer::with_library(Arc::new(animations.into()));
+        player.set_play_animation("default", true, None).unwrap();
+        player.set_root_motion(Some(RootMotion {
+            source: RootMotionSource::Root,
+            rotation: false,
+        }));
+        let mut instances = Vec::new();
+
+        for _ in 0..6 {
+            player.update(&mut instances, 0.1);
+        }
+        assert_eq!(player.take_root_motion().translation, Vec2::new(10.0, 0.0));
+        assert_eq!(player.take_root_motion(), RootMotionDelta::default());
+        // 参照保持在第一帧的位置，其它实例一起被剥离
+        assert_eq!(instances[0].transform.tx, Twips::ZERO);
+        assert_eq!(instances[1].transform.tx, Twips::from_pixels(-10.0));
+
+        // 循环回绕时参照回到起点，但位移继续累加
+        for _ in 0..10 {
+            player.update(&mut instances, 0.1);
+        }
+        assert_eq!(player.take_root_motion().translation, Vec2::new(10.0, 0.0));
+        assert_eq!(instances[0].transform.tx, Twips::ZERO);
+
+        player.set_root_motion(None);
+        player.update(&mut instances, 0.0);
+        assert_eq!(player.take_root_motion(), RootMotionDelta::default());
+        assert_eq!(instances[0].transform.tx, Twips::from_pixels(10.0));
+    }
+
+    #[test]
+    fn snapshot_restores_identical_playback() -> Result<()> {
+        let mut player = test_player();
+        let mut instances = Vec::new();
+        player.set_speed(1.5);
+        player.set_frame_exact(true);
+        player.set_flip_x(true);
+        player.set_opacity(0.5);
+        player.set_instance_color_transform(
+            "arm",
+            swf::ColorTransform {
+                g_add: 30,
+                ..Default::default()
+            },
+        )?;
+        for _ in 0..3 {
+            player.update(&mut instances, 0.1);
+        }
+        player.clip_mut("arm")?.set_speed(0.5);
+        player.update(&mut instances, 0.1);
+        // 累积了不足一帧的时间
+        assert!(player.frame_progress() > 0.0);
+
+        let json = serde_json::to_string(&player.snapshot())?;
+        let state: PlayerState = serde_json::from_str(&json)?;
+        assert_eq!(state, player.snapshot());
+        let mut restored = test_player();
+        restored.restore(&state)?;
+        assert_eq!(restored.snapshot(), state);
+        assert!(restored.is_frame_exact());
+        assert!(restored.flip_x());
+        assert_eq!(restored.frame_progress(), player.frame_progress());
+
+        let mut restored_instances = Vec::new();
+        for _ in 0..8 {
+            player.update(&mut instances, 0.1);
+            restored.update(&mut restored_instances, 0.1);
+            assert_eq!(instances, restored_instances);
+        }
+
+        let mut missing = state.clone();
+        missing.animation = Some("missing".to_owned());
+        assert!(restored.restore(&missing).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn frame_exact_playback() -> Result<()> {
+        let mut player = test_player();
+        player.set_frame_exact(true);
+        player.set_event_queue_enabled(true);
+        let mut instances = Vec::new();
+
+        for _ in 0..3 {
+            player.update(&mut instances, 0.03);
+        }
+        assert_eq!(player.current_frame(), 0);
+        player.update(&mut instances, 0.03);
+        assert_eq!(player.current_time(), 0.1);
+        assert!((player.frame_progress() - 0.2).abs() < 1e-3);
+
+        // 高刷新率下播放一遍，播放头始终落在帧上，事件只触发一次
+        player.seek(0.0);
+        for _ in 0..59 {
+            player.update(&mut instances, 1.0 / 60.0);
+            let frames = player.current_time() * 10.0;
+            assert_eq!(frames, frames.round());
+        }
+        let hits = player
+            .drain_events()
+            .into_iter()
+            .filter(|event| matches!(event, PlayerEvent::FrameEvent { name, .. } if name == "hit"))
+            .count();
+        assert_eq!(hits, 1);
+        assert_eq!(player.current_frame(), 9);
+        Ok(())
+    }
+
+    #[test]
+    fn frame_interpolation() {
+        let mut animations = test_animations();
+        let timeline = &mut animations.animations.get_mut("default").unwrap().timeline;
+        let body = &mut timeline.get_mut(&1).unwrap().transforms;
+        let mut moved = body[0].clone();
+        moved.time = 0.1;
+        moved.matrix.tx = Twips::from_pixels(10.0);
+        body.push(moved);
+        let mut player = AnimationPlayer::with_library(Arc::new(animations.into()));
+        player.set_play_animation("default", true, None).unwrap();
+        player.set_frame_exact(true);
+        player.set_frame_interpolation(true);
+        let mut instances = Vec::new();
+
+        player.update(&mut instances, 0.05);
+        assert_eq!(instances[0].transform.tx, Twips::from_pixels(5.0));
+        player.update(&mut instances, 0.05);
+        assert_eq!(instances[0].transform.tx, Twips::from_pixels(10.0));
+        player.update(&mut instances, 0.05);
+        assert_eq!(instances[0].transform.tx, Twips::from_pixels(10.0));
+
+        player.set_frame_interpolation(false);
+        player.seek(0.0);
+        player.update(&mut instances, 0.05);
+        assert_eq!(instances[0].transform.tx, Twips::ZERO);
+
+        // 插值设置在关闭逐帧播放后保留，也可以在开启之前设置
+        player.set_frame_interpolation(true);
+        player.set_frame_exact(false);
+        player.set_frame_exact(true);
+        player.seek(0.0);
+        player.update(&mut instances, 0.05);
+        assert_eq!(instances[0].transform.tx, Twips::from_pixels(5.0));
+        player.set_frame_exact(false);
+        let state = player.snapshot();
+        let mut restored = AnimationPlayer::with_library(player.library.clone());
+        restored.restore(&state).unwrap();
+        restored.set_frame_exact(true);
+        restored.seek(0.0);
+        restored.update(&mut instances, 0.05);
+        assert_eq!(instances[0].transform.tx, Twips::from_pixels(5.0));
+    }
+}
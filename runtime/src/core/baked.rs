@@ -0,0 +1,330 @@
+use std::{collections::HashMap, ops::Range, sync::Arc};
+
+use anyhow::Result;
+use swf::{CharacterId, Depth};
+
+use crate::parser::{parse_shape::matrix::Matrix, types::BlendMode};
+
+use super::{
+    AnimationLibrary, InstanceAncestor, LoopMode, RenderFilter, RuntimeInstance, SampleOptions,
+    TimeAdvance, advance_clock, error::RuntimeError, sample, time_to_frame,
+};
+
+/// 烘焙表中的单个实例，只保存逐帧变化的数据，滤镜、祖先链和遮罩层存放在动画共享的表中
+#[derive(Debug, Clone)]
+struct BakedInstance {
+    id: CharacterId,
+    instance_id: u64,
+    depth: Depth,
+    transform: Matrix,
+    color_transform: swf::ColorTransform,
+    blend: BlendMode,
+    /// 在[`BakedAnimation::filters`]中的范围
+    filters: Range<usize>,
+    mask_layer: Option<u64>,
+    /// 在[`BakedAnimation::ancestors`]中的下标
+    ancestors: usize,
+    /// 在[`BakedAnimation::masks`]中的下标
+    masked_by: usize,
+}
+
+/// 烘焙后的单个动画，所有帧的实例连续存放在一张表中
+#[derive(Debug, Clone)]
+pub struct BakedAnimation {
+    duration: f32,
+    instances: Vec<BakedInstance>,
+    /// 第`i`帧的实例为`instances[frame_offsets[i]..frame_offsets[i + 1]]`
+    frame_offsets: Vec<usize>,
+    /// 所有实例的滤镜，按实例依次存放
+    filters: Vec<RenderFilter>,
+    /// 去重后的祖先链，各帧的实例共享
+    ancestors: Vec<Arc<[InstanceAncestor]>>,
+    /// 去重后的遮罩层列表，各帧的实例共享
+    masks: Vec<Arc<[u64]>>,
+}
+
+impl BakedAnimation {
+    fn new(duration: f32, frame_count: usize) -> Self {
+        let mut frame_offsets = Vec::with_capacity(frame_count + 1);
+        frame_offsets.push(0);
+        Self {
+            duration,
+            instances: Vec::new(),
+            frame_offsets,
+            filters: Vec::new(),
+            ancestors: Vec::new(),
+            masks: Vec::new(),
+        }
+    }
+
+    /// 追加一帧的实例
+    fn push_frame(&mut self, instances: &[RuntimeInstance]) {
+        for instance in instances {
+            let filters = self.filters.len()..self.filters.len() + instance.filters.len();
+            self.filters.extend_from_slice(&instance.filters);
+            let ancestors = intern(&mut self.ancestors, &instance.ancestors);
+            let masked_by = intern(&mut self.masks, &instance.masked_by);
+            self.instances.push(BakedInstance {
+                id: instance.id,
+                instance_id: instance.instance_id,
+                depth: instance.depth,
+                transform: instance.transform,
+                color_transform: instance.color_transform,
+                blend: instance.blend,
+                filters,
+                mask_layer: instance.mask_layer,
+                ancestors,
+                masked_by,
+            });
+        }
+        self.frame_offsets.push(self.instances.len());
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_offsets.len() - 1
+    }
+
+    /// 输出指定帧的实例，超出范围时为最后一帧。
+    ///
+    /// 从头覆盖`instances`中已有的实例，复用它们滤镜列表的空间
+    pub fn frame_into(&self, frame: usize, instances: &mut Vec<RuntimeInstance>) {
+        let frame = frame.min(self.frame_count() - 1);
+        let records = &self.instances[self.frame_offsets[frame]..self.frame_offsets[frame + 1]];
+        instances.truncate(records.len());
+        for (draw_order, record) in records.iter().enumerate() {
+            let mut filters = instances
+                .get_mut(draw_order)
+                .map(|instance| std::mem::take(&mut instance.filters))
+                .unwrap_or_default();
+            filters.clear();
+            filters.extend_from_slice(&self.filters[record.filters.clone()]);
+            let instance = RuntimeInstance {
+                id: record.id,
+                instance_id: record.instance_id,
+                depth: record.depth,
+                ancestors: Arc::clone(&self.ancestors[record.ancestors]),
+                draw_order: draw_order as u32,
+                transform: record.transform,
+                color_transform: record.color_transform,
+                blend: record.blend,
+                filters,
+                mask_layer: record.mask_layer,
+                masked_by: Arc::clone(&self.masks[record.masked_by]),
+            };
+            match instances.get_mut(draw_order) {
+                Some(existing) => *existing = instance,
+                None => instances.push(instance),
+            }
+        }
+    }
+}
+
+/// 在`table`中查找内容相同的列表，没有时加入，返回其下标
+fn intern<T: PartialEq>(table: &mut Vec<Arc<[T]>>, list: &Arc<[T]>) -> usize {
+    table
+        .iter()
+        .position(|interned| interned == list)
+        .unwrap_or_else(|| {
+            table.push(Arc::clone(list));
+            table.len() - 1
+        })
+}
+
+/// 按固定帧率预先计算好每一帧的实例，用内存换取播放时的计算量，通过[`BakedAnimations::bake`]生成
+#[derive(Debug, Clone)]
+pub struct BakedAnimations {
+    fps: f32,
+    animations: HashMap<String, BakedAnimation>,
+}
+
+impl BakedAnimations {
+    /// 以`fps`烘焙资源中的所有动画，使用默认皮肤
+    pub fn bake(library: &AnimationLibrary, fps: f32) -> Self {
+        Self::bake_with(library, fps, &SampleOptions::default())
+    }
+
+    /// 以`fps`和指定的皮肤烘焙资源中的所有动画
+    pub fn bake_with(library: &AnimationLibrary, fps: f32, options: &SampleOptions) -> Self {
+        let animations = library
+            .animations()
+            .iter()
+            .map(|(name, animation)| {
+                let frame_count = time_to_frame(animation.duration, fps) as usize + 1;
+                let mut baked = BakedAnimation::new(animation.duration, frame_count);
+                for frame in 0..frame_count {
+                    // 动画一定存在
+                    let instances =
+                        sample(library, name, frame as f32 / fps, options).unwrap_or_default();
+                    baked.push_frame(&instances);
+                }
+                (name.clone(), baked)
+            })
+            .collect();
+        Self { fps, animations }
+    }
+
+    pub fn fps(&self) -> f32 {
+        self.fps
+    }
+
+    pub fn animation(&self, name: &str) -> Option<&BakedAnimation> {
+        self.animations.get(name)
+    }
+}
+
+/// 播放烘焙后的动画，每次更新只按帧号取出实例，不做查找和递归。
+///
+/// 帧率与动画帧率相同时输出与[`AnimationPlayer`](super::AnimationPlayer)完全一致；
+/// 不支持子影片控制、实例覆盖、换肤和事件。
+#[derive(Debug)]
+pub struct BakedPlayer {
+    baked: Arc<BakedAnimations>,
+    animation: Option<String>,
+    current_time: f32,
+    speed: f32,
+    loop_mode: LoopMode,
+    completed_loops: u32,
+    reversed: bool,
+    playing: bool,
+    /// 复用的时间区间列表，避免每次更新都分配
+    time_advance: TimeAdvance,
+}
+
+impl BakedPlayer {
+    pub fn new(baked: Arc<BakedAnimations>) -> Self {
+        Self {
+            baked,
+            animation: None,
+            current_time: 0.0,
+            speed: 1.0,
+            loop_mode: LoopMode::default(),
+            completed_loops: 0,
+            reversed: false,
+            playing: true,
+            time_advance: TimeAdvance::default(),
+        }
+    }
+
+    fn current_animation(&self) -> Option<&BakedAnimation> {
+        self.animation
+            .as_ref()
+            .and_then(|name| self.baked.animation(name))
+    }
+
+    /// 设置播放动画，速度为负数时从动画末尾开始倒放
+    pub fn set_play_animation(&mut self, name: &str, loop_mode: impl Into<LoopMode>) -> Result<()> {
+        let Some(animation) = self.baked.animation(name) else {
+            return Err(RuntimeError::AnimationNotFound(name.to_owned()).into());
+        };
+        self.current_time = if self.speed < 0.0 {
+            animation.duration
+        } else {
+            0.0
+        };
+        self.animation = Some(name.to_owned());
+        self.loop_mode = loop_mode.into();
+        self.completed_loops = 0;
+        self.reversed = false;
+        Ok(())
+    }
+
+    /// 输出当前时间的帧，然后推进播放头；暂停时只输出不推进，跳转后的画面同样会输出
+    pub fn update(&mut self, active_instances: &mut Vec<RuntimeInstance>, delta_time: f32) {
+        let Some(duration) = self.current_animation().map(BakedAnimation::duration) else {
+            return;
+        };
+        let previous_time = self.current_time;
+        if self.playing {
+            let direction = if self.reversed { -1.0 } else { 1.0 };
+            advance_clock(
+                &mut self.time_advance,
+                &mut self.current_time,
+                self.loop_mode,
+                &mut self.completed_loops,
+                &mut self.reversed,
+                delta_time * self.speed * direction,
+                duration,
+            );
+            if self.time_advance.finished {
+                self.playing = false;
+            }
+        }
+
+        let frame = time_to_frame(previous_time, self.baked.fps()) as usize;
+        let animation = self.current_animation().unwrap();
+        animation.frame_into(frame, active_instances);
+    }
+
+    /// 跳转到指定时间（秒），超出范围会被限制在动画时长内
+    pub fn seek(&mut self, time: f32) {
+        if let Some(duration) = self.current_animation().map(BakedAnimation::duration) {
+            self.current_time = time.clamp(0.0, duration);
+        }
+    }
+
+    /// 当前时间所在的帧，从0开始计数
+    pub fn current_frame(&self) -> u32 {
+        time_to_frame(self.current_time, self.baked.fps())
+    }
+
+    pub fn current_time(&self) -> f32 {
+        self.current_time
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = if speed.is_finite() { speed } else { 0.0 };
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test::{ids, test_animations, test_player};
+    use super::*;
+
+    #[test]
+    fn matches_player() -> Result<()> {
+        let baked = Arc::new(test_animations().bake(10.0));
+        assert_eq!(baked.animation("default").unwrap().frame_count(), 11);
+        let mut baked_player = BakedPlayer::new(baked);
+        baked_player.set_play_animation("default", LoopMode::Loop)?;
+        let mut player = test_player();
+
+        let mut instances = Vec::new();
+        let mut baked_instances = Vec::new();
+        for _ in 0..30 {
+            player.update(&mut instances, 0.1);
+            baked_player.update(&mut baked_instances, 0.1);
+            assert_eq!(baked_instances, instances);
+            assert_eq!(baked_player.current_frame(), player.current_frame());
+        }
+
+        // 暂停时跳转，更新输出跳转后的画面
+        baked_player.set_playing(false);
+        player.set_playing(false);
+        baked_player.seek(0.5);
+        player.seek(0.5);
+        baked_player.update(&mut baked_instances, 0.1);
+        player.update(&mut instances, 0.1);
+        assert_eq!(ids(&baked_instances), vec![1, 3]);
+        assert_eq!(baked_instances, instances);
+        assert_eq!(baked_player.current_time(), 0.5);
+        assert!(baked_player.set_play_animation("missing", true).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::{BTreeMap, HashMap};
+
+use swf::{CharacterId, Depth, Rectangle, Twips};
+
+use crate::parser::{Animation, DepthTimeline, MovieClip};
+
+use super::{RuntimeInstance, filter::Filter, find_key_frame};
+
+/// 滤镜扩展后的包围盒，滤镜按从内到外的顺序依次扩展
+fn expand_by_filters(bounds: Rectangle<Twips>, filters: &[Filter]) -> Rectangle<Twips> {
+    filters
+        .iter()
+        .fold(bounds, |bounds, filter| filter.calculate_dest_rect(bounds))
+}
+
+/// 实例在世界坐标中的包围盒，图形没有包围盒时返回`None`
+pub(super) fn instance_bounds(
+    instance: &RuntimeInstance,
+    shape_bounds: &HashMap<CharacterId, Rectangle<Twips>>,
+    expand_filters: bool,
+) -> Option<Rectangle<Twips>> {
+    let bounds = instance.transform * shape_bounds.get(&instance.id)?.clone();
+    Some(if expand_filters {
+        expand_by_filters(bounds, &instance.filters)
+    } else {
+        bounds
+    })
+}
+
+/// 逐帧计算动画所有帧的包围盒并集。
+///
+/// 子影片按其自身所有帧的并集计算并缓存，皮肤子影片因此也包含了所有皮肤，结果偏保守。
+pub(super) fn compute_max_bounds(
+    animation: &Animation,
+    children_clip: &HashMap<CharacterId, MovieClip>,
+    shape_bounds: &HashMap<CharacterId, Rectangle<Twips>>,
+    frame_rate: f32,
+    clip_bounds: &mut HashMap<CharacterId, Rectangle<Twips>>,
+) -> Option<Rectangle<Twips>> {
+    let mut calculator = BoundsCalculator {
+        children_clip,
+        shape_bounds,
+        frame_rate,
+        clip_bounds,
+    };
+    let bounds = calculator.timeline_bounds(&animation.timeline, animation.duration);
+    bounds.is_valid().then_some(bounds)
+}
+
+struct BoundsCalculator<'a> {
+    children_clip: &'a HashMap<CharacterId, MovieClip>,
+    shape_bounds: &'a HashMap<CharacterId, Rectangle<Twips>>,
+    frame_rate: f32,
+    /// 子影片本地坐标中所有帧的包围盒
+    clip_bounds: &'a mut HashMap<CharacterId, Rectangle<Twips>>,
+}
+
+impl BoundsCalculator<'_> {
+    fn timeline_bounds(
+        &mut self,
+        timeline: &BTreeMap<Depth, DepthTimeline>,
+        duration: f32,
+    ) -> Rectangle<Twips> {
+        let mut bounds = Rectangle::INVALID;
+        let frames = (duration * self.frame_rate).ceil().max(1.0) as u32;
+        for frame in 0..frames {
+            let time = frame as f32 / self.frame_rate;
+            for depth_timeline in timeline.values() {
+                let placements = &depth_timeline.placement;
+                let (Some(start_placement), _) = find_key_frame(time, placements) else {
+                    continue;
+                };
+                let Some(id) = placements[start_placement].resource_id() else {
+                    continue;
+                };
+                let (Some(start), _) = find_key_frame(time, &depth_timeline.transforms) else {
+                    continue;
+                };
+                let transform = depth_timeline.transforms[start].matrix;
+                let local_bounds = match self.children_clip.get(&id) {
+                    Some(clip) => {
+                        let filters: Vec<Filter> = placements[start_placement]
+                            .filters()
+                            .iter()
+                            .map(Filter::from)
+                            .collect();
+                        expand_by_filters(transform * self.clip_bounds(clip), &filters)
+                    }
+                    None => match self.shape_bounds.get(&id) {
+                        Some(shape_bounds) => transform * shape_bounds.clone(),
+                        None => continue,
+                    },
+                };
+                bounds = bounds.union(&local_bounds);
+            }
+        }
+        bounds
+    }
+
+    fn clip_bounds(&mut self, clip: &MovieClip) -> Rectangle<Twips> {
+        if let Some(bounds) = self.clip_bounds.get(&clip.id()) {
+            return bounds.clone();
+        }
+        // 先占位，避免异常数据中子影片互相嵌套导致无限递归
+        self.clip_bounds.insert(clip.id(), Rectangle::INVALID);
+        let bounds = self.timeline_bounds(clip.timeline(), clip.duration());
+        self.clip_bounds.insert(clip.id(), bounds.clone());
+        bounds
+    }
+}
@@ -0,0 +1,109 @@
+use crate::parser::MovieClip;
+
+/// 子影片跳转目标，帧号从0开始
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameTarget {
+    Frame(u32),
+    Label(String),
+}
+
+impl From<u32> for FrameTarget {
+    fn from(frame: u32) -> Self {
+        FrameTarget::Frame(frame)
+    }
+}
+
+impl From<&str> for FrameTarget {
+    fn from(label: &str) -> Self {
+        FrameTarget::Label(label.to_owned())
+    }
+}
+
+impl From<String> for FrameTarget {
+    fn from(label: String) -> Self {
+        FrameTarget::Label(label)
+    }
+}
+
+impl FrameTarget {
+    /// 解析为子影片中的时间，找不到标签时返回`None`
+    pub(super) fn resolve(&self, clip: &MovieClip, frame_rate: f32) -> Option<f32> {
+        match self {
+            FrameTarget::Frame(frame) => Some((*frame as f32 / frame_rate).min(clip.duration())),
+            FrameTarget::Label(label) => clip
+                .labels()
+                .iter()
+                .find(|l| &l.name == label)
+                .map(|l| l.time)
+                .or_else(|| {
+                    clip.events()
+                        .iter()
+                        .find(|event| &event.name == label)
+                        .map(|event| event.time)
+                }),
+        }
+    }
+}
+
+/// 实例名或实例路径对应的子影片播放控制，作用于所有匹配的实例，没有控制的子影片会自由循环播放。
+///
+/// 停止时固定的时间记录在各个实例上，同名实例各自停在自己的帧
+#[derive(Debug, Clone)]
+pub(super) struct ClipControl {
+    pub(super) playing: bool,
+    pub(super) speed: f32,
+    /// 等待下次遍历时应用的跳转
+    pub(super) goto: Option<FrameTarget>,
+    /// 本次遍历中是否已有实例应用了跳转，遍历结束后清除跳转
+    pub(super) goto_applied: bool,
+}
+
+impl Default for ClipControl {
+    fn default() -> Self {
+        Self {
+            playing: true,
+            speed: 1.0,
+            goto: None,
+            goto_applied: false,
+        }
+    }
+}
+
+/// 通过[`AnimationPlayer::clip_mut`](super::AnimationPlayer::clip_mut)获取，
+/// 控制指定实例名或路径的子影片，效果在下一次`update`时生效
+pub struct ClipController<'a> {
+    pub(super) control: &'a mut ClipControl,
+}
+
+impl ClipController<'_> {
+    /// 停在当前帧
+    pub fn stop(&mut self) -> &mut Self {
+        self.control.playing = false;
+        self
+    }
+
+    /// 从当前帧继续播放
+    pub fn play(&mut self) -> &mut Self {
+        self.control.playing = true;
+        self
+    }
+
+    /// 跳转到指定帧或标签并停止
+    pub fn goto_and_stop(&mut self, target: impl Into<FrameTarget>) -> &mut Self {
+        self.control.goto = Some(target.into());
+        self.control.playing = false;
+        self
+    }
+
+    /// 跳转到指定帧或标签并继续播放
+    pub fn goto_and_play(&mut self, target: impl Into<FrameTarget>) -> &mut Self {
+        self.control.goto = Some(target.into());
+        self.play()
+    }
+
+    /// 设置子影片自身的播放速度，不影响它内部的子影片
+    pub fn set_speed(&mut self, speed: f32) -> &mut Self {
+        self.control.speed = if speed.is_finite() { speed } else { 0.0 };
+        self
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use super::RuntimeInstance;
+
+/// 相邻两次输出之间活动实例的变化，方便引擎复用已经生成的精灵实体，
+/// 通过[`AnimationPlayer::instance_diff`](super::AnimationPlayer::instance_diff)获取
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InstanceDiff {
+    /// 新出现的实例，值为实例在本次输出中的下标
+    pub spawned: Vec<usize>,
+    /// 变换、颜色、混合模式、滤镜或绘制顺序发生变化的实例，值为实例在本次输出中的下标
+    pub updated: Vec<usize>,
+    /// 已经消失的实例id
+    pub despawned: Vec<u64>,
+}
+
+impl InstanceDiff {
+    pub fn is_empty(&self) -> bool {
+        self.spawned.is_empty() && self.updated.is_empty() && self.despawned.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.spawned.clear();
+        self.updated.clear();
+        self.despawned.clear();
+    }
+}
+
+/// 记录上一次输出的实例，用于计算差异
+#[derive(Debug, Default)]
+pub(super) struct DiffTracker {
+    /// Key为实例id，Value为实例和最后一次出现时的代数
+    previous: HashMap<u64, (RuntimeInstance, u64)>,
+    generation: u64,
+    diff: InstanceDiff,
+}
+
+impl DiffTracker {
+    pub(super) fn track(&mut self, instances: &[RuntimeInstance]) {
+        self.diff.clear();
+        self.generation += 1;
+        let generation = self.generation;
+        for (index, instance) in instances.iter().enumerate() {
+            match self.previous.get_mut(&instance.instance_id()) {
+                Some((previous, seen)) => {
+                    if previous != instance {
+                        previous.clone_from(instance);
+                        self.diff.updated.push(index);
+                    }
+                    *seen = generation;
+                }
+                None => {
+                    self.previous
+                        .insert(instance.instance_id(), (instance.clone(), generation));
+                    self.diff.spawned.push(index);
+                }
+            }
+        }
+        let despawned = &mut self.diff.despawned;
+        self.previous.retain(|id, (_, seen)| {
+            let alive = *seen == generation;
+            if !alive {
+                despawned.push(*id);
+            }
+            alive
+        });
+        despawned.sort_unstable();
+    }
+
+    pub(super) fn diff(&self) -> &InstanceDiff {
+        &self.diff
+    }
+}
@@ -0,0 +1,105 @@
+use swf::Fixed8;
+
+use crate::parser::parse_shape::matrix::Matrix;
+
+/// 作用于整个播放器的显示设置，在遍历时作为根时间轴继承的属性
+#[derive(Debug, Clone)]
+pub(super) struct DisplaySettings {
+    pub(super) root_transform: Matrix,
+    pub(super) flip_x: bool,
+    pub(super) flip_y: bool,
+    pub(super) color_transform: swf::ColorTransform,
+    pub(super) opacity: f32,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            root_transform: Matrix::IDENTITY,
+            flip_x: false,
+            flip_y: false,
+            color_transform: swf::ColorTransform::IDENTITY,
+            opacity: 1.0,
+        }
+    }
+}
+
+impl DisplaySettings {
+    /// 根时间轴的世界变换，翻转以动画的注册点为中心，之后再应用根变换
+    pub(super) fn transform(&self) -> Matrix {
+        if !self.flip_x && !self.flip_y {
+            return self.root_transform;
+        }
+        let flip = Matrix::scale(
+            if self.flip_x { -1.0 } else { 1.0 },
+            if self.flip_y { -1.0 } else { 1.0 },
+        );
+        self.root_transform * flip
+    }
+
+    /// 根时间轴的颜色变换，包括不透明度
+    pub(super) fn color_transform(&self) -> swf::ColorTransform {
+        let mut color_transform = self.color_transform;
+        if self.opacity != 1.0 {
+            color_transform *= swf::ColorTransform {
+                a_multiply: Fixed8::from_f32(self.opacity),
+                ..swf::ColorTransform::IDENTITY
+            };
+        }
+        color_transform
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use glam::Vec2;
+    use swf::{Rectangle, Twips};
+
+    use super::super::{
+        AnimationLibrary, AnimationPlayer,
+        test::{square_mesh, test_animations},
+    };
+    use super::*;
+
+    #[test]
+    fn player_settings_apply_to_output() -> Result<()> {
+        let square = square_mesh(10.0);
+        let rect = |x_min: f64, x_max: f64| Rectangle {
+            x_min: Twips::from_pixels(x_min),
+            x_max: Twips::from_pixels(x_max),
+            y_min: Twips::ZERO,
+            y_max: Twips::from_pixels(10.0),
+        };
+        let mut library = AnimationLibrary::from(test_animations());
+        library.set_shape_bounds([(1, rect(0.0, 10.0))]);
+        library.set_shape_meshes([(1, &square)]);
+        let mut player = AnimationPlayer::with_library(Arc::new(library));
+        player.set_play_animation("default", true, None)?;
+        player.set_playing(false);
+
+        // 以注册点为中心翻转后再平移
+        player.set_flip_x(true);
+        player.set_root_transform(Matrix::translate(Twips::from_pixels(100.0), Twips::ZERO));
+        player.set_opacity(0.5);
+        player.set_global_color_transform(swf::ColorTransform {
+            r_add: 40,
+            ..swf::ColorTransform::IDENTITY
+        });
+        let mut instances = Vec::new();
+        player.update(&mut instances, 0.1);
+        assert_eq!(player.bounds(), Some(rect(90.0, 100.0)));
+        assert!(player.hit_test(Vec2::new(95.0, 5.0)).is_some());
+        assert!(player.hit_test(Vec2::new(5.0, 5.0)).is_none());
+        let color_transform = instances[0].color_transform();
+        assert_eq!(color_transform.a_multiply.to_f32(), 0.5);
+        assert_eq!(color_transform.r_add, 40);
+
+        player.seek(0.3);
+        let arm = player.instance_transform("arm").unwrap();
+        assert_eq!((arm.a, arm.tx), (-1.0, Twips::from_pixels(100.0)));
+        Ok(())
+    }
+}
@@ -13,4 +13,28 @@ pub enum RuntimeError {
 
     #[error("skin part `{0}` not found")]
     SkinPartNotFound(String),
+
+    #[error("label `{0}` not found")]
+    LabelNotFound(String),
+
+    #[error("instance `{0}` not found")]
+    InstanceNotFound(String),
+
+    #[error("frame `{0}` out of range, animation has {1} frames")]
+    FrameOutOfRange(u32, u32),
+
+    #[error("symbol `{0}` not found")]
+    SymbolNotFound(String),
+
+    #[error("skin set `{0}` not found")]
+    SkinSetNotFound(String),
+
+    #[error("state `{0}` not found")]
+    StateNotFound(String),
+
+    #[error("parameter `{0}` not found")]
+    ParameterNotFound(String),
+
+    #[error("parameter `{0}` has a different type")]
+    ParameterTypeMismatch(String),
 }
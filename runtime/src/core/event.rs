@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use swf::CharacterId;
+
+use crate::parser::Animation;
+
+pub(super) type FrameEventCallback = Box<dyn Fn() + Send + Sync + 'static>;
+
+/// 以帧为单位比较时间时允许的浮点误差
+pub(super) const FRAME_EPSILON: f32 = 1.0e-3;
+
+/// 播放器产生的事件，通过[`AnimationPlayer::drain_events`](super::AnimationPlayer::drain_events)拉取，
+/// 适合ECS这类不方便使用回调的架构
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlayerEvent {
+    /// 开始播放动画
+    Started { animation: String },
+    /// 完成一轮循环（往返播放时每到达一端算一轮）
+    Looped { animation: String, loop_count: u32 },
+    /// 播放完成
+    Completed { animation: String },
+    /// 帧事件，`instance_path`为空表示根时间轴上的事件，否则为子影片的实例路径
+    FrameEvent {
+        animation: String,
+        name: String,
+        payload: Option<String>,
+        instance_path: String,
+    },
+    /// 时间轴上开始播放音效
+    SoundTriggered {
+        animation: String,
+        sound_id: CharacterId,
+    },
+    /// 播放头进入普通帧标签
+    LabelEntered { animation: String, label: String },
+}
+
+/// 监听器句柄，用于移除单个监听器
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ListenerId(pub(super) u64);
+
+pub(super) struct FrameEventListener {
+    pub(super) id: ListenerId,
+    /// 只在这个动画中触发
+    pub(super) animation: String,
+    pub(super) callback: FrameEventCallback,
+}
+
+/// 调用播放`animation`时监听`event`的回调
+pub(super) fn notify_listeners(
+    listeners: &HashMap<String, Vec<FrameEventListener>>,
+    animation: &str,
+    event: &str,
+) {
+    if let Some(listeners) = listeners.get(event) {
+        listeners
+            .iter()
+            .filter(|listener| listener.animation == animation)
+            .for_each(|listener| (listener.callback)());
+    }
+}
+
+/// 播放头经过的一段时间区间，`from > to`表示倒放
+#[derive(Debug, Clone, Copy)]
+pub(super) struct TimeSegment {
+    pub(super) from: f32,
+    pub(super) to: f32,
+    /// 是否包含终点所在帧的事件
+    pub(super) inclusive_end: bool,
+}
+
+impl TimeSegment {
+    pub(super) fn new(from: f32, to: f32) -> Self {
+        Self {
+            from,
+            to,
+            inclusive_end: false,
+        }
+    }
+
+    /// 以帧为单位判断播放头是否经过了`time`所在的帧：
+    /// 正放时区间为`[from, to)`，倒放时为`(to, from]`，`inclusive_end`为真时同时包含终点所在帧。
+    pub(super) fn passes(&self, time: f32, frame_rate: f32) -> bool {
+        let from = self.from * frame_rate;
+        let to = self.to * frame_rate;
+        let frame = (time * frame_rate).round();
+        if from <= to {
+            frame >= from - FRAME_EPSILON
+                && (frame < to - FRAME_EPSILON
+                    || (self.inclusive_end && frame <= to + FRAME_EPSILON))
+        } else {
+            frame <= from + FRAME_EPSILON
+                && (frame > to + FRAME_EPSILON
+                    || (self.inclusive_end && frame >= to - FRAME_EPSILON))
+        }
+    }
+
+    pub(super) fn is_forward(&self) -> bool {
+        self.from <= self.to
+    }
+}
+
+/// 一次更新中入队的事件，同时记录每个事件在这次推进中的位置（秒），
+/// 用于把子影片和根时间轴的事件按经过的先后合并
+pub(super) struct EventSink<'a> {
+    pub(super) queue: &'a mut Vec<PlayerEvent>,
+    pub(super) progress: &'a mut Vec<f32>,
+}
+
+impl EventSink<'_> {
+    pub(super) fn push(&mut self, event: PlayerEvent, progress: f32) {
+        self.queue.push(event);
+        self.progress.push(progress);
+    }
+
+    /// 按位置稳定排序从`first`开始入队的事件，同一位置保持入队顺序。
+    ///
+    /// 一次更新的事件很少，插入排序不需要额外的空间
+    pub(super) fn sort_from(&mut self, first: usize) {
+        let events = &mut self.queue[first..];
+        let progress = &mut self.progress[..];
+        debug_assert_eq!(events.len(), progress.len());
+        for index in 1..events.len() {
+            let mut current = index;
+            while current > 0 && progress[current - 1] > progress[current] {
+                progress.swap(current - 1, current);
+                events.swap(current - 1, current);
+                current -= 1;
+            }
+        }
+    }
+}
+
+/// 播放头在一段区间内经过的时间轴标记，值为在动画中对应列表里的下标。
+///
+/// 变体的顺序即同一帧内的触发顺序：标签、事件、音效
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) enum Marker {
+    Label(usize),
+    Event(usize),
+    Sound(usize),
+}
+
+/// 触发根时间轴上播放头经过的标签、帧事件和音效，按经过的先后顺序触发。
+///
+/// `progress`为区间起点在这次推进中的位置，`markers`为复用的缓冲区，避免每次更新都分配
+pub(super) fn dispatch_timeline_events(
+    animation: &Animation,
+    frame_rate: f32,
+    segment: TimeSegment,
+    listeners: &HashMap<String, Vec<FrameEventListener>>,
+    mut sink: Option<&mut EventSink<'_>>,
+    markers: &mut Vec<(f32, Marker)>,
+    progress: f32,
+) {
+    markers.clear();
+    markers.extend(
+        animation
+            .labels
+            .iter()
+            .enumerate()
+            .filter(|(_, label)| segment.passes(label.time, frame_rate))
+            .map(|(index, label)| (label.time, Marker::Label(index))),
+    );
+    markers.extend(
+        animation
+            .events
+            .iter()
+            .enumerate()
+            .filter(|(_, event)| segment.passes(event.time, frame_rate))
+            .map(|(index, event)| (event.time, Marker::Event(index))),
+    );
+    markers.extend(
+        animation
+            .sounds
+            .iter()
+            .enumerate()
+            .filter(|(_, sound)| segment.passes(sound.time, frame_rate))
+            .map(|(index, sound)| (sound.time, Marker::Sound(index))),
+    );
+    if markers.is_empty() {
+        return;
+    }
+    // 同一帧按标签、事件、音效的顺序，同类按列表中的顺序，不稳定排序不需要额外的缓冲区
+    if segment.is_forward() {
+        markers.sort_unstable_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    } else {
+        markers.sort_unstable_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    }
+
+    for &(time, marker) in markers.iter() {
+        let progress = progress + (time - segment.from).abs();
+        match marker {
+            Marker::Label(index) => {
+                if let Some(sink) = sink.as_deref_mut() {
+                    sink.push(
+                        PlayerEvent::LabelEntered {
+                            animation: animation.name.clone(),
+                            label: animation.labels[index].name.clone(),
+                        },
+                        progress,
+                    );
+                }
+            }
+            Marker::Event(index) => {
+                let event = &animation.events[index];
+                notify_listeners(listeners, &animation.name, &event.name);
+                if let Some(sink) = sink.as_deref_mut() {
+                    sink.push(
+                        PlayerEvent::FrameEvent {
+                            animation: animation.name.clone(),
+                            name: event.name.clone(),
+                            payload: event.payload.clone(),
+                            instance_path: String::new(),
+                        },
+                        progress,
+                    );
+                }
+            }
+            Marker::Sound(index) => {
+                if let Some(sink) = sink.as_deref_mut() {
+                    sink.push(
+                        PlayerEvent::SoundTriggered {
+                            animation: animation.name.clone(),
+                            sound_id: animation.sounds[index].id,
+                        },
+                        progress,
+                    );
+                }
+            }
+        }
+    }
+}
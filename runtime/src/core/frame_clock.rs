@@ -0,0 +1,46 @@
+use super::event::FRAME_EPSILON;
+
+/// 逐帧播放的时钟，累积时间并以整帧推进，见
+/// [`AnimationPlayer::set_frame_exact`](super::AnimationPlayer::set_frame_exact)
+#[derive(Debug, Default)]
+pub(super) struct FrameClock {
+    /// 还不足一帧的时间（秒）
+    pub(super) accumulator: f32,
+    /// 是否在帧之间插值
+    pub(super) interpolate: bool,
+    /// 本次输出的插值因子，从输出的帧到下一帧
+    pub(super) interpolation: Option<f32>,
+}
+
+impl FrameClock {
+    pub(super) fn new(interpolate: bool) -> Self {
+        Self {
+            interpolate,
+            ..Default::default()
+        }
+    }
+
+    /// 累积`delta_time`，返回本次推进的整帧数
+    pub(super) fn step(&mut self, delta_time: f32, frame_rate: f32) -> u32 {
+        self.accumulator += delta_time.abs();
+        let frames = (self.accumulator * frame_rate + FRAME_EPSILON).floor();
+        self.accumulator = (self.accumulator - frames / frame_rate).max(0.0);
+        let frames = frames as u32;
+        // 输出的帧落后于播放头，推进过的帧按完整的一帧插值
+        self.interpolation = self
+            .interpolate
+            .then(|| (frames as f32 + self.progress(frame_rate)).min(1.0));
+        frames
+    }
+
+    /// 到下一帧的进度，范围为`[0, 1)`
+    pub(super) fn progress(&self, frame_rate: f32) -> f32 {
+        (self.accumulator * frame_rate).min(1.0)
+    }
+
+    /// 跳转后丢弃不足一帧的时间
+    pub(super) fn reset(&mut self) {
+        self.accumulator = 0.0;
+        self.interpolation = None;
+    }
+}
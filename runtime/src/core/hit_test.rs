@@ -0,0 +1,90 @@
+use glam::Vec2;
+use swf::{CharacterId, Point, Twips};
+
+use crate::parser::parse_shape::tessellator::Mesh;
+
+use super::RuntimeInstance;
+
+/// 点击测试命中的实例
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceHit {
+    /// 命中图形的稳定实例id
+    pub instance_id: u64,
+    pub character_id: CharacterId,
+    /// 命中图形所在子影片的实例路径，根时间轴上的图形为空
+    pub instance_path: String,
+    pub draw_order: u32,
+}
+
+/// 用于点击测试的三角形，坐标为图形本地坐标（像素）
+#[derive(Debug, Default)]
+pub(super) struct HitMesh {
+    /// 填充的三角形，同时也是作为遮罩时的形状
+    fills: Vec<[Vec2; 3]>,
+    /// 线条的三角形
+    strokes: Vec<[Vec2; 3]>,
+}
+
+impl From<&Mesh> for HitMesh {
+    fn from(mesh: &Mesh) -> Self {
+        let mut hit_mesh = HitMesh::default();
+        for draw in &mesh.draws {
+            let vertex = |index: &u32| {
+                let vertex = &draw.vertices[*index as usize];
+                Vec2::new(vertex.x, vertex.y)
+            };
+            for (i, triangle) in draw.indices.chunks_exact(3).enumerate() {
+                let triangle = [
+                    vertex(&triangle[0]),
+                    vertex(&triangle[1]),
+                    vertex(&triangle[2]),
+                ];
+                if ((i + 1) * 3) as u32 <= draw.mask_index_count {
+                    hit_mesh.fills.push(triangle);
+                } else {
+                    hit_mesh.strokes.push(triangle);
+                }
+            }
+        }
+        hit_mesh
+    }
+}
+
+impl HitMesh {
+    fn contains(&self, point: Vec2, include_strokes: bool) -> bool {
+        let hit = |triangles: &[[Vec2; 3]]| {
+            triangles
+                .iter()
+                .any(|triangle| triangle_contains(triangle, point))
+        };
+        hit(&self.fills) || (include_strokes && hit(&self.strokes))
+    }
+}
+
+fn triangle_contains([a, b, c]: &[Vec2; 3], point: Vec2) -> bool {
+    let d1 = (point - b).perp_dot(a - b);
+    let d2 = (point - c).perp_dot(b - c);
+    let d3 = (point - a).perp_dot(c - a);
+    let has_negative = d1 < 0.0 || d2 < 0.0 || d3 < 0.0;
+    let has_positive = d1 > 0.0 || d2 > 0.0 || d3 > 0.0;
+    !(has_negative && has_positive)
+}
+
+/// 世界坐标（像素）中的点是否落在实例的图形上，作为遮罩时不包括线条
+pub(super) fn instance_contains(
+    instance: &RuntimeInstance,
+    hit_mesh: &HitMesh,
+    point: Vec2,
+    as_mask: bool,
+) -> bool {
+    let Some(inverse) = instance.transform.inverse() else {
+        return false;
+    };
+    let world = Point::new(
+        Twips::from_pixels(point.x as f64),
+        Twips::from_pixels(point.y as f64),
+    );
+    let local = inverse * world;
+    let local = Vec2::new(local.x.to_pixels() as f32, local.y.to_pixels() as f32);
+    hit_mesh.contains(local, !as_mask)
+}
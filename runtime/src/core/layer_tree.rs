@@ -0,0 +1,139 @@
+use crate::parser::types::BlendMode;
+
+use super::{RuntimeInstance, filter::Filter};
+
+/// 树形输出中的节点
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayerNode {
+    /// 直接绘制的实例，值为在扁平输出中的下标
+    Instance(usize),
+    /// 需要先绘制到离屏纹理再合成的子影片
+    Group(LayerGroup),
+}
+
+/// 带有滤镜或非Normal混合模式的子影片，子节点按绘制顺序排列
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerGroup {
+    /// 子影片的稳定实例id
+    pub instance_id: u64,
+    /// 合成到父级时使用的混合模式
+    pub blend: BlendMode,
+    /// 合成前应用的滤镜，按应用顺序排列
+    pub filters: Vec<Filter>,
+    pub children: Vec<LayerNode>,
+}
+
+/// 扁平输出的树形视图。
+///
+/// 扁平输出把子影片的滤镜和混合模式复制到每个图形上，多个图形组成的子影片无法正确表现
+/// 发光等滤镜以及Layer、Alpha、Erase等混合模式。树形视图中这些子影片成为离屏分组，
+/// 分组内的实例应按Normal混合、不带滤镜绘制到分组的纹理中，再由分组统一应用滤镜并按其混合模式合成。
+/// 遮罩仍通过实例的[`RuntimeInstance::mask_layer`]和[`RuntimeInstance::masked_by`]表示。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LayerTree {
+    pub roots: Vec<LayerNode>,
+}
+
+impl LayerTree {
+    /// 按祖先链从扁平输出构建树形视图，`instances`需要是播放器输出的完整列表
+    pub fn build(instances: &[RuntimeInstance]) -> Self {
+        let mut roots = Vec::new();
+        // 当前打开的分组，从外到内
+        let mut open: Vec<LayerGroup> = Vec::new();
+        for (index, instance) in instances.iter().enumerate() {
+            let ancestors = &instance.ancestors;
+            let mut groups = ancestors
+                .iter()
+                .enumerate()
+                .filter(|(_, ancestor)| ancestor.is_offscreen());
+            // 保留仍然包含这个实例的分组
+            let mut common = 0;
+            for group in &open {
+                match groups.next() {
+                    Some((_, ancestor)) if ancestor.instance_id == group.instance_id => common += 1,
+                    _ => break,
+                }
+            }
+            close_groups(&mut open, &mut roots, common);
+            let groups = ancestors
+                .iter()
+                .enumerate()
+                .filter(|(_, ancestor)| ancestor.is_offscreen())
+                .skip(common);
+            for (level, ancestor) in groups {
+                let group = LayerGroup {
+                    instance_id: ancestor.instance_id,
+                    blend: ancestor.blend_mode,
+                    filters: instance.ancestor_filters(level).to_vec(),
+                    children: Vec::new(),
+                };
+                open.push(group);
+            }
+            match open.last_mut() {
+                Some(group) => group.children.push(LayerNode::Instance(index)),
+                None => roots.push(LayerNode::Instance(index)),
+            }
+        }
+        close_groups(&mut open, &mut roots, 0);
+        Self { roots }
+    }
+}
+
+/// 关闭分组直到只剩`len`个，关闭的分组加入其父级
+fn close_groups(open: &mut Vec<LayerGroup>, roots: &mut Vec<LayerNode>, len: usize) {
+    while open.len() > len {
+        let group = open.pop().unwrap();
+        match open.last_mut() {
+            Some(parent) => parent.children.push(LayerNode::Group(group)),
+            None => roots.push(LayerNode::Group(group)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use serde_json::json;
+
+    use super::super::{
+        AnimationLibrary, AnimationPlayer, SampleOptions, sample,
+        test::{placement, test_animations},
+    };
+    use super::*;
+
+    #[test]
+    fn groups_offscreen_clips() -> Result<()> {
+        let mut animations = test_animations();
+        let timeline = &mut animations.animations.get_mut("default").unwrap().timeline;
+        let mut arm = placement(0.2, Some(10));
+        arm["blend_mode"] = json!("Layer");
+        arm["filters"] = json!([{ "GlowFilter": { "color": [255, 0, 0, 255], "blur_x": 4.0, "blur_y": 4.0, "strength": 1.0, "flags": 0 } }]);
+        timeline.get_mut(&2).unwrap().placement[0] = serde_json::from_value(arm)?;
+        let mut player = AnimationPlayer::with_library(Arc::new(animations.into()));
+        player.set_play_animation("default", true, None)?;
+        let mut instances = Vec::new();
+        player.seek(0.2);
+        player.update(&mut instances, 0.1);
+
+        let tree = player.layer_tree();
+        let [LayerNode::Instance(0), LayerNode::Group(group)] = tree.roots.as_slice() else {
+            panic!("{:?}", tree.roots);
+        };
+        assert_eq!(group.instance_id, instances[1].ancestors()[0].instance_id);
+        assert_eq!(group.blend, BlendMode::Layer);
+        assert!(matches!(group.filters[..], [Filter::GlowFilter(_)]));
+        assert_eq!(group.children, [LayerNode::Instance(1)]);
+
+        // 没有滤镜和混合模式时与扁平输出一致
+        let tree = LayerTree::build(&sample(
+            &AnimationLibrary::from(test_animations()),
+            "default",
+            0.3,
+            &SampleOptions::default(),
+        )?);
+        assert_eq!(tree.roots, [LayerNode::Instance(0), LayerNode::Instance(1)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use swf::{CharacterId, Rectangle, Twips};
+
+use crate::parser::{
+    Animation, Animations, MovieClip,
+    parse_shape::{Graphic, tessellator::Mesh},
+};
+
+use super::{SkinCatalog, bounds::compute_max_bounds, hit_test::HitMesh};
+
+/// 只读的动画资源，多个[`AnimationPlayer`](super::AnimationPlayer)可以通过`Arc`共享同一份数据，
+/// 播放器本身只保存时间、皮肤和控制状态。
+#[derive(Debug, Default)]
+pub struct AnimationLibrary {
+    /// 原flash动画帧率
+    frame_rate: f32,
+    /// 动画数据
+    animations: HashMap<String, Animation>,
+    /// 动画子影片资源
+    children_clip: HashMap<CharacterId, MovieClip>,
+    /// 可换肤的部位、皮肤和套装
+    skin_catalog: SkinCatalog,
+    /// 图形在本地坐标中的包围盒
+    shape_bounds: HashMap<CharacterId, Rectangle<Twips>>,
+    /// 用于点击测试的图形三角形
+    hit_meshes: HashMap<CharacterId, HitMesh>,
+}
+
+impl AnimationLibrary {
+    pub fn new(
+        mut animations: HashMap<String, Animation>,
+        children_clip: HashMap<CharacterId, MovieClip>,
+        frame_rate: f32,
+    ) -> Self {
+        // 帧事件按时间排序，保证触发顺序
+        animations.values_mut().for_each(|animation| {
+            animation.events.sort_by(|a, b| a.time.total_cmp(&b.time));
+        });
+        let skin_catalog = SkinCatalog::new(&animations, &children_clip);
+        Self {
+            frame_rate,
+            animations,
+            children_clip,
+            skin_catalog,
+            shape_bounds: HashMap::new(),
+            hit_meshes: HashMap::new(),
+        }
+    }
+
+    /// 设置图形的包围盒，并预先计算每个动画所有帧的包围盒[`Animation::max_bounds`]。
+    /// 需要在共享给播放器之前设置
+    pub fn set_shape_bounds(
+        &mut self,
+        shape_bounds: impl IntoIterator<Item = (CharacterId, Rectangle<Twips>)>,
+    ) {
+        self.shape_bounds.extend(shape_bounds);
+        let mut clip_bounds = HashMap::new();
+        for animation in self.animations.values_mut() {
+            let max_bounds = compute_max_bounds(
+                animation,
+                &self.children_clip,
+                &self.shape_bounds,
+                self.frame_rate,
+                &mut clip_bounds,
+            );
+            animation.set_max_bounds(max_bounds);
+        }
+    }
+
+    /// 设置用于点击测试的图形网格，需要在共享给播放器之前设置
+    pub fn set_shape_meshes<'m>(
+        &mut self,
+        meshes: impl IntoIterator<Item = (CharacterId, &'m Mesh)>,
+    ) {
+        self.hit_meshes.extend(
+            meshes
+                .into_iter()
+                .map(|(id, mesh)| (id, HitMesh::from(mesh))),
+        );
+    }
+
+    /// 使用解析得到的图形设置包围盒和点击测试网格，
+    /// 见[`Self::set_shape_bounds`]和[`Self::set_shape_meshes`]
+    pub fn set_graphics(&mut self, graphics: &HashMap<CharacterId, Graphic>) {
+        self.set_shape_bounds(
+            graphics
+                .iter()
+                .map(|(id, graphic)| (*id, graphic.shape.shape_bounds.clone())),
+        );
+        self.set_shape_meshes(
+            graphics
+                .iter()
+                .map(|(id, graphic)| (*id, &graphic.lyon_mesh)),
+        );
+    }
+
+    pub fn shape_bounds(&self) -> &HashMap<CharacterId, Rectangle<Twips>> {
+        &self.shape_bounds
+    }
+
+    pub(super) fn hit_mesh(&self, id: CharacterId) -> Option<&HitMesh> {
+        self.hit_meshes.get(&id)
+    }
+
+    /// 添加皮肤套装，与同名套装合并。需要在共享给播放器之前添加
+    pub fn add_skin_set<I, P, S>(&mut self, name: &str, skins: I)
+    where
+        I: IntoIterator<Item = (P, S)>,
+        P: Into<String>,
+        S: Into<String>,
+    {
+        self.skin_catalog.insert_skin_set(name, skins);
+    }
+
+    pub fn skin_catalog(&self) -> &SkinCatalog {
+        &self.skin_catalog
+    }
+
+    pub fn frame_rate(&self) -> f32 {
+        self.frame_rate
+    }
+
+    pub fn animation(&self, name: &str) -> Option<&Animation> {
+        self.animations.get(name)
+    }
+
+    pub fn animations(&self) -> &HashMap<String, Animation> {
+        &self.animations
+    }
+
+    pub fn animation_names(&self) -> Vec<&String> {
+        self.animations.keys().collect()
+    }
+
+    pub fn clip(&self, id: CharacterId) -> Option<&MovieClip> {
+        self.children_clip.get(&id)
+    }
+
+    /// 按链接名查找子影片
+    pub fn clip_by_name(&self, name: &str) -> Option<&MovieClip> {
+        self.children_clip
+            .values()
+            .find(|clip| clip.name() == Some(name))
+    }
+
+    pub fn children_clip(&self) -> &HashMap<CharacterId, MovieClip> {
+        &self.children_clip
+    }
+}
+
+impl From<Animations> for AnimationLibrary {
+    fn from(animations: Animations) -> Self {
+        let mut library = Self::new(
+            animations.animations,
+            animations.children_clip,
+            animations.meta.frame_rate,
+        );
+        for (name, skins) in animations.skin_sets {
+            library.add_skin_set(&name, skins);
+        }
+        library
+    }
+}
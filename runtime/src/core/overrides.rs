@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+use swf::CharacterId;
+
+/// 运行时对单个实例的覆盖，不修改动画资源
+#[derive(Debug, Clone, Default)]
+pub(super) struct InstanceOverride {
+    /// 隐藏的实例不输出，但子影片仍然继续播放
+    pub(super) hidden: bool,
+    /// 叠加在实例原有颜色变换上的颜色变换
+    pub(super) color_transform: Option<swf::ColorTransform>,
+    /// 替换后的资源id
+    pub(super) character: Option<CharacterId>,
+}
+
+/// 查找实例的覆盖，优先匹配实例路径，其次匹配实例名。
+///
+/// 没有名字的实例用深度代替，只能通过完整路径匹配，避免误伤其它时间轴上相同深度的实例。
+/// `path_buffer`用于拼接完整路径，由调用方复用。
+pub(super) fn find_override<'a>(
+    overrides: &'a HashMap<String, InstanceOverride>,
+    instance_path: &str,
+    name: &str,
+    named: bool,
+    path_buffer: &mut String,
+) -> Option<&'a InstanceOverride> {
+    let by_path = if instance_path.is_empty() {
+        overrides.get(name)
+    } else {
+        path_buffer.clear();
+        path_buffer.push_str(instance_path);
+        path_buffer.push('/');
+        path_buffer.push_str(name);
+        overrides.get(path_buffer.as_str())
+    };
+    by_path.or_else(|| {
+        if named && !instance_path.is_empty() {
+            overrides.get(name)
+        } else {
+            None
+        }
+    })
+}
@@ -0,0 +1,195 @@
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use super::{AnimationPlayer, RuntimeInstance};
+
+/// [`PlayerPool`]中播放器的句柄
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PlayerId {
+    index: usize,
+    /// 槽位被复用后旧句柄失效
+    generation: u32,
+}
+
+struct Slot {
+    generation: u32,
+    entry: Option<Entry>,
+}
+
+struct Entry {
+    player: AnimationPlayer,
+    /// 每个播放器独占的输出缓冲区，更新时复用
+    output: Vec<RuntimeInstance>,
+}
+
+/// 批量更新大量播放器，每个播放器的输出写入各自复用的缓冲区。
+///
+/// 开启`parallel`特性后通过rayon并行更新，帧事件和完成回调会在rayon的工作线程中调用，
+/// 回调本身已经要求`Send + Sync`，但回调之间的执行顺序不确定，需要有序处理时使用事件队列。
+#[derive(Default)]
+pub struct PlayerPool {
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+    /// 新播放器输出缓冲区的初始容量
+    output_capacity: usize,
+}
+
+impl PlayerPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 预留`players`个播放器的位置，每个播放器的输出缓冲区预分配`instances`个实例
+    pub fn with_capacity(players: usize, instances: usize) -> Self {
+        Self {
+            slots: Vec::with_capacity(players),
+            free: Vec::new(),
+            output_capacity: instances,
+        }
+    }
+
+    pub fn insert(&mut self, player: AnimationPlayer) -> PlayerId {
+        let entry = Entry {
+            player,
+            output: Vec::with_capacity(self.output_capacity),
+        };
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.entry = Some(entry);
+                PlayerId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    entry: Some(entry),
+                });
+                PlayerId {
+                    index: self.slots.len() - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: PlayerId) -> Option<AnimationPlayer> {
+        let slot = self
+            .slots
+            .get_mut(id.index)
+            .filter(|slot| slot.generation == id.generation)?;
+        let entry = slot.entry.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(id.index);
+        Some(entry.player)
+    }
+
+    fn entry(&self, id: PlayerId) -> Option<&Entry> {
+        self.slots
+            .get(id.index)
+            .filter(|slot| slot.generation == id.generation)?
+            .entry
+            .as_ref()
+    }
+
+    pub fn get(&self, id: PlayerId) -> Option<&AnimationPlayer> {
+        self.entry(id).map(|entry| &entry.player)
+    }
+
+    pub fn get_mut(&mut self, id: PlayerId) -> Option<&mut AnimationPlayer> {
+        self.slots
+            .get_mut(id.index)
+            .filter(|slot| slot.generation == id.generation)?
+            .entry
+            .as_mut()
+            .map(|entry| &mut entry.player)
+    }
+
+    /// 播放器最近一次更新输出的实例
+    pub fn output(&self, id: PlayerId) -> Option<&[RuntimeInstance]> {
+        self.entry(id).map(|entry| entry.output.as_slice())
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 所有播放器及其最近一次的输出
+    pub fn iter(&self) -> impl Iterator<Item = (PlayerId, &AnimationPlayer, &[RuntimeInstance])> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let entry = slot.entry.as_ref()?;
+            let id = PlayerId {
+                index,
+                generation: slot.generation,
+            };
+            Some((id, &entry.player, entry.output.as_slice()))
+        })
+    }
+
+    /// 更新所有播放器
+    pub fn update(&mut self, delta_time: f32) {
+        let update = |slot: &mut Slot| {
+            if let Some(entry) = slot.entry.as_mut() {
+                entry.player.update(&mut entry.output, delta_time);
+            }
+        };
+        #[cfg(feature = "parallel")]
+        self.slots.par_iter_mut().for_each(update);
+        #[cfg(not(feature = "parallel"))]
+        self.slots.iter_mut().for_each(update);
+    }
+}
+
+/// 批量更新时播放器会被移动到其它线程
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<AnimationPlayer>();
+    assert_send_sync::<PlayerPool>();
+};
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+
+    use super::super::{AnimationLibrary, test::test_animations};
+    use super::*;
+
+    #[test]
+    fn updates_into_own_buffers() -> Result<()> {
+        let library = Arc::new(AnimationLibrary::from(test_animations()));
+        let mut pool = PlayerPool::with_capacity(4, 8);
+        let mut ids = Vec::new();
+        for speed in [1.0, 2.0, 3.0] {
+            let mut player = AnimationPlayer::with_library(library.clone());
+            player.set_play_animation("default", true, None)?;
+            player.set_speed(speed);
+            ids.push(pool.insert(player));
+        }
+        pool.update(0.1);
+        pool.update(0.1);
+        // 速度不同的播放器各自推进
+        let outputs: Vec<_> = ids
+            .iter()
+            .map(|id| pool.output(*id).unwrap().len())
+            .collect();
+        assert_eq!(outputs, vec![1, 2, 2]);
+
+        let removed = pool.remove(ids[1]).unwrap();
+        assert_eq!(removed.speed(), 2.0);
+        assert!(pool.get(ids[1]).is_none());
+        let reused = pool.insert(AnimationPlayer::with_library(library));
+        assert_ne!(reused, ids[1]);
+        assert!(pool.remove(ids[1]).is_none());
+        assert_eq!(pool.len(), 3);
+        assert_eq!(pool.iter().count(), 3);
+        Ok(())
+    }
+}
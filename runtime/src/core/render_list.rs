@@ -0,0 +1,526 @@
+//! 把[`RuntimeInstance`]和图形网格转换为有序的绘制批次，与具体的引擎无关。
+//!
+//! 所有批次共享同一份顶点和索引缓冲区，顶点已经变换到世界坐标（像素）并应用了颜色变换。
+//! 命令按顺序执行：
+//! - [`RenderCommand::Draw`]：绘制一个批次，相邻的同纹理、同混合模式的绘制会合并；
+//! - 遮罩：`PushMask`之后绘制的是遮罩的形状，`ActivateMask`之后的内容只在遮罩内可见，
+//!   `DeactivateMask`之后会再次绘制遮罩的形状用于清除，`PopMask`结束清除。与模板缓冲的用法一致；
+//! - 滤镜：`PushFilterGroup`到`PopFilterGroup`之间的内容先绘制到离屏纹理，应用滤镜后再合成。
+//!
+//! 着色器统一为`纹理颜色 * color + color_add`，纯色绘制的纹理颜色视为白色。
+
+use std::{collections::HashMap, ops::Range};
+
+use swf::CharacterId;
+
+use crate::parser::{
+    parse_shape::tessellator::{Draw, DrawType, Mesh},
+    types::BlendMode,
+};
+
+use super::{RuntimeInstance, filter::Filter};
+
+/// 世界坐标中的顶点
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderVertex {
+    /// 世界坐标（像素）
+    pub position: [f32; 2],
+    /// 渐变或位图的纹理坐标，纯色绘制时为0
+    pub uv: [f32; 2],
+    /// 与纹理颜色相乘的颜色，范围为`[0, 1]`。纯色绘制时为应用颜色变换后的最终颜色
+    pub color: [f32; 4],
+    /// 颜色变换的加数，范围为`[-1, 1]`。纯色绘制时已经合并到`color`中，为0
+    pub color_add: [f32; 4],
+}
+
+/// 批次使用的纹理
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BatchTexture {
+    /// 纯色
+    Color,
+    /// 图形网格中的第`index`个渐变，见[`Mesh::gradients`]
+    Gradient {
+        character_id: CharacterId,
+        index: usize,
+    },
+    Bitmap {
+        bitmap_id: CharacterId,
+        is_smoothed: bool,
+        is_repeating: bool,
+    },
+}
+
+/// 一次绘制调用
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrawBatch {
+    pub texture: BatchTexture,
+    pub blend: BlendMode,
+    /// 在[`RenderList::indices`]中的范围
+    pub indices: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenderCommand {
+    Draw(DrawBatch),
+    /// 开始绘制遮罩的形状
+    PushMask,
+    /// 遮罩绘制完成，之后的内容只在所有生效的遮罩内可见
+    ActivateMask,
+    /// 开始清除最内层的遮罩，之后会再次绘制它的形状
+    DeactivateMask,
+    /// 最内层的遮罩清除完成
+    PopMask,
+    /// 之后的内容绘制到离屏分组中
+    PushFilterGroup {
+        /// 应用滤镜的子影片的实例id
+        instance_id: u64,
+        /// 在[`RenderList::filters`]中的范围，按应用顺序排列
+        filters: Range<usize>,
+    },
+    /// 对最内层的分组应用滤镜并合成
+    PopFilterGroup,
+}
+
+/// 遮罩和滤镜分组的嵌套，按外层到内层排列
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Mask(u64),
+    Filter(u64),
+}
+
+/// 本次构建中已经绘制过的遮罩层
+#[derive(Debug, Clone)]
+struct MaskLayer {
+    /// 遮罩所在时间轴的层级，根时间轴为0
+    level: usize,
+    /// 遮罩形状的绘制命令在`commands`中的范围
+    draws: Range<usize>,
+}
+
+/// 有序的绘制命令列表，缓冲区在多次构建之间复用
+#[derive(Debug, Default)]
+pub struct RenderList {
+    vertices: Vec<RenderVertex>,
+    indices: Vec<u32>,
+    commands: Vec<RenderCommand>,
+    filters: Vec<Filter>,
+    /// 当前打开的遮罩和滤镜分组
+    scopes: Vec<Scope>,
+    /// 当前实例需要的遮罩和滤镜分组
+    target: Vec<(usize, Scope)>,
+    /// 正在绘制形状的遮罩层
+    building_mask: Option<u64>,
+    mask_layers: HashMap<u64, MaskLayer>,
+}
+
+impl RenderList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 按绘制顺序转换`instances`，`meshes`按资源id查找图形网格，找不到网格的实例会被跳过
+    pub fn build<'m>(
+        &mut self,
+        instances: &[RuntimeInstance],
+        meshes: impl Fn(CharacterId) -> Option<&'m Mesh>,
+    ) {
+        self.clear();
+        for instance in instances {
+            let Some(mesh) = meshes(instance.id) else {
+                continue;
+            };
+            match instance.mask_layer {
+                Some(layer) => self.draw_mask(instance, mesh, layer),
+                None => self.draw_content(instance, mesh),
+            }
+        }
+        self.finish_mask();
+        self.pop_scopes(0);
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+        self.commands.clear();
+        self.filters.clear();
+        self.scopes.clear();
+        self.building_mask = None;
+        self.mask_layers.clear();
+    }
+
+    pub fn commands(&self) -> &[RenderCommand] {
+        &self.commands
+    }
+
+    pub fn vertices(&self) -> &[RenderVertex] {
+        &self.vertices
+    }
+
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    /// 滤镜分组使用的滤镜
+    pub fn filters(&self) -> &[Filter] {
+        &self.filters
+    }
+
+    fn draw_mask(&mut self, instance: &RuntimeInstance, mesh: &Mesh, layer: u64) {
+        if self.building_mask != Some(layer) {
+            self.finish_mask();
+            // 遮罩本身不应用滤镜，只进入它所在层级之外的分组
+            let level = mask_level(instance, layer);
+            self.collect_target(instance, Some(level));
+            if !self.enter_target(instance) {
+                return;
+            }
+            self.commands.push(RenderCommand::PushMask);
+            self.building_mask = Some(layer);
+            let start = self.commands.len();
+            self.mask_layers.insert(
+                layer,
+                MaskLayer {
+                    level,
+                    draws: start..start,
+                },
+            );
+        }
+        for draw in &mesh.draws {
+            // 遮罩只使用填充，不包括线条
+            let fills = (draw.mask_index_count as usize).min(draw.indices.len());
+            let indices = &draw.indices[..fills];
+            self.push_draw(
+                instance,
+                draw,
+                indices,
+                BatchTexture::Color,
+                BlendMode::Normal,
+            );
+        }
+        if let Some(mask) = self.mask_layers.get_mut(&layer) {
+            mask.draws.end = self.commands.len();
+        }
+    }
+
+    fn draw_content(&mut self, instance: &RuntimeInstance, mesh: &Mesh) {
+        self.finish_mask();
+        self.collect_target(instance, None);
+        if !self.enter_target(instance) {
+            return;
+        }
+        for draw in &mesh.draws {
+            let texture = match &draw.draw_type {
+                DrawType::Color => BatchTexture::Color,
+                DrawType::Gradient { gradient, .. } => BatchTexture::Gradient {
+                    character_id: instance.id,
+                    index: *gradient,
+                },
+                DrawType::Bitmap(bitmap) => BatchTexture::Bitmap {
+                    bitmap_id: bitmap.bitmap_id,
+                    is_smoothed: bitmap.is_smoothed,
+                    is_repeating: bitmap.is_repeating,
+                },
+            };
+            self.push_draw(instance, draw, &draw.indices, texture, instance.blend);
+        }
+    }
+
+    /// 正在绘制的遮罩在遇到其它实例时生效
+    fn finish_mask(&mut self) {
+        if let Some(layer) = self.building_mask.take() {
+            self.commands.push(RenderCommand::ActivateMask);
+            self.scopes.push(Scope::Mask(layer));
+        }
+    }
+
+    /// 计算实例需要的遮罩和滤镜分组，`mask_level`不为空时只保留这个层级之外的分组
+    fn collect_target(&mut self, instance: &RuntimeInstance, mask_level: Option<usize>) {
+        self.target.clear();
+        for layer in instance.masked_by.iter() {
+            // 没有绘制过的遮罩视为空遮罩，下面会跳过这个实例
+            let key = self
+                .mask_layers
+                .get(layer)
+                .map_or(usize::MAX, |mask| mask.level * 2);
+            self.target.push((key, Scope::Mask(*layer)));
+        }
+        // 同一层级上遮罩在外，子影片的滤镜在内
+        for (level, ancestor) in instance.ancestors.iter().enumerate() {
+            if ancestor.filter_count > 0 {
+                self.target
+                    .push((level * 2 + 1, Scope::Filter(ancestor.instance_id)));
+            }
+        }
+        if let Some(mask_level) = mask_level {
+            self.target.retain(|(key, _)| *key < mask_level * 2);
+        }
+        self.target.sort_by_key(|(key, _)| *key);
+    }
+
+    /// 关闭多余的分组并打开缺少的分组，实例被空遮罩遮住时返回`false`
+    fn enter_target(&mut self, instance: &RuntimeInstance) -> bool {
+        let empty_mask = self.target.iter().any(|(_, scope)| match scope {
+            Scope::Mask(layer) => !self.mask_layers.contains_key(layer),
+            Scope::Filter(_) => false,
+        });
+        if empty_mask {
+            return false;
+        }
+        let common = self
+            .scopes
+            .iter()
+            .zip(&self.target)
+            .take_while(|(scope, (_, target))| *scope == target)
+            .count();
+        self.pop_scopes(common);
+        for index in common..self.target.len() {
+            let scope = self.target[index].1;
+            match scope {
+                Scope::Mask(layer) => {
+                    let draws = self.mask_layers[&layer].draws.clone();
+                    self.commands.push(RenderCommand::PushMask);
+                    self.redraw(draws);
+                    self.commands.push(RenderCommand::ActivateMask);
+                }
+                Scope::Filter(instance_id) => self.push_filter_group(instance, instance_id),
+            }
+            self.scopes.push(scope);
+        }
+        true
+    }
+
+    /// 打开子影片的滤镜分组，滤镜取自实例继承的滤镜中属于这个子影片的部分
+    fn push_filter_group(&mut self, instance: &RuntimeInstance, instance_id: u64) {
+        let Some(index) = instance
+            .ancestors
+            .iter()
+            .position(|ancestor| ancestor.instance_id == instance_id)
+        else {
+            return;
+        };
+        let own = instance.ancestor_filters(index);
+        let start = self.filters.len();
+        self.filters.extend_from_slice(own);
+        self.commands.push(RenderCommand::PushFilterGroup {
+            instance_id,
+            filters: start..self.filters.len(),
+        });
+    }
+
+    /// 关闭分组直到只剩`len`层
+    fn pop_scopes(&mut self, len: usize) {
+        while self.scopes.len() > len {
+            match self.scopes.pop().unwrap() {
+                Scope::Mask(layer) => {
+                    let draws = self.mask_layers[&layer].draws.clone();
+                    self.commands.push(RenderCommand::DeactivateMask);
+                    self.redraw(draws);
+                    self.commands.push(RenderCommand::PopMask);
+                }
+                Scope::Filter(_) => self.commands.push(RenderCommand::PopFilterGroup),
+            }
+        }
+    }
+
+    /// 再次绘制遮罩的形状
+    fn redraw(&mut self, draws: Range<usize>) {
+        for index in draws {
+            let command = self.commands[index].clone();
+            self.commands.push(command);
+        }
+    }
+
+    fn push_draw(
+        &mut self,
+        instance: &RuntimeInstance,
+        draw: &Draw,
+        indices: &[u32],
+        texture: BatchTexture,
+        blend: BlendMode,
+    ) {
+        if indices.is_empty() {
+            return;
+        }
+        let base = self.vertices.len() as u32;
+        let uv_matrix = match &draw.draw_type {
+            DrawType::Color => None,
+            DrawType::Gradient { matrix, .. } => Some(matrix),
+            DrawType::Bitmap(bitmap) => Some(&bitmap.matrix),
+        };
+        let transform = instance.transform;
+        let color_transform = instance.color_transform;
+        let multiply = [
+            color_transform.r_multiply.to_f32(),
+            color_transform.g_multiply.to_f32(),
+            color_transform.b_multiply.to_f32(),
+            color_transform.a_multiply.to_f32(),
+        ];
+        let add = [
+            color_transform.r_add as f32 / 255.0,
+            color_transform.g_add as f32 / 255.0,
+            color_transform.b_add as f32 / 255.0,
+            color_transform.a_add as f32 / 255.0,
+        ];
+        self.vertices.extend(draw.vertices.iter().map(|vertex| {
+            let (x, y) = (vertex.x, vertex.y);
+            let position = [
+                transform.a * x + transform.c * y + transform.tx.to_pixels() as f32,
+                transform.b * x + transform.d * y + transform.ty.to_pixels() as f32,
+            ];
+            let uv = uv_matrix.map_or([0.0; 2], |m| {
+                [
+                    m[0][0] * x + m[1][0] * y + m[2][0],
+                    m[0][1] * x + m[1][1] * y + m[2][1],
+                ]
+            });
+            let vertex_color = [
+                vertex.color.r as f32 / 255.0,
+                vertex.color.g as f32 / 255.0,
+                vertex.color.b as f32 / 255.0,
+                vertex.color.a as f32 / 255.0,
+            ];
+            let (color, color_add) = if texture == BatchTexture::Color {
+                let color = std::array::from_fn(|i| {
+                    (vertex_color[i] * multiply[i] + add[i]).clamp(0.0, 1.0)
+                });
+                (color, [0.0; 4])
+            } else {
+                (std::array::from_fn(|i| vertex_color[i] * multiply[i]), add)
+            };
+            RenderVertex {
+                position,
+                uv,
+                color,
+                color_add,
+            }
+        }));
+
+        let start = self.indices.len();
+        self.indices
+            .extend(indices.iter().map(|index| base + *index));
+        let end = self.indices.len();
+        // 与上一个相邻的同类绘制合并
+        if let Some(RenderCommand::Draw(batch)) = self.commands.last_mut()
+            && batch.texture == texture
+            && batch.blend == blend
+            && batch.indices.end == start
+        {
+            batch.indices.end = end;
+            return;
+        }
+        self.commands.push(RenderCommand::Draw(DrawBatch {
+            texture,
+            blend,
+            indices: start..end,
+        }));
+    }
+}
+
+/// 遮罩所在时间轴的层级。遮罩是形状时为形状所在的时间轴，是子影片时为子影片所在的时间轴
+fn mask_level(instance: &RuntimeInstance, layer: u64) -> usize {
+    instance
+        .ancestors
+        .iter()
+        .position(|ancestor| ancestor.instance_id == layer)
+        .unwrap_or(instance.ancestors.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use serde_json::json;
+    use swf::Twips;
+
+    use crate::parser::{Animations, parse_shape::matrix::Matrix};
+
+    use super::super::{
+        AnimationPlayer,
+        test::{placement, square_mesh, test_animations},
+    };
+    use super::*;
+
+    #[test]
+    fn batches_masks_and_filters() -> Result<()> {
+        let meshes: HashMap<CharacterId, Mesh> = [
+            (1, square_mesh(10.0)),
+            (2, square_mesh(20.0)),
+            (3, square_mesh(20.0)),
+        ]
+        .into();
+        let build = |animations: Animations| -> Result<RenderList> {
+            let mut player = AnimationPlayer::with_library(Arc::new(animations.into()));
+            player.set_play_animation("default", true, None)?;
+            player.set_root_transform(Matrix::translate(Twips::from_pixels(100.0), Twips::ZERO));
+            player.set_global_color_transform(swf::ColorTransform {
+                a_multiply: swf::Fixed8::from_f32(0.5),
+                ..Default::default()
+            });
+            let mut instances = Vec::new();
+            player.seek(0.2);
+            player.update(&mut instances, 0.1);
+            let mut render_list = RenderList::new();
+            render_list.build(&instances, |id| meshes.get(&id));
+            Ok(render_list)
+        };
+
+        // 相邻的同类绘制合并为一个批次，顶点变换到世界坐标并应用颜色变换
+        let render_list = build(test_animations())?;
+        let [RenderCommand::Draw(batch)] = render_list.commands() else {
+            panic!("{:?}", render_list.commands());
+        };
+        assert_eq!(batch.texture, BatchTexture::Color);
+        assert_eq!(batch.indices, 0..12);
+        assert_eq!(render_list.vertices().len(), 8);
+        assert_eq!(&render_list.indices()[6..9], &[4, 5, 6]);
+        let corner = render_list.vertices()[6];
+        assert_eq!(corner.position, [120.0, 20.0]);
+        assert_eq!(corner.color, [1.0, 1.0, 1.0, 0.5]);
+
+        // 深度1作为遮罩遮住带模糊滤镜的子影片
+        let mut animations = test_animations();
+        let timeline = &mut animations.animations.get_mut("default").unwrap().timeline;
+        let mut mask = placement(0.0, Some(1));
+        mask["clip_depth"] = json!(2);
+        timeline.get_mut(&1).unwrap().placement[0] = serde_json::from_value(mask)?;
+        let mut arm = placement(0.2, Some(10));
+        arm["filters"] = json!([{ "BlurFilter": { "blur_x": 4.0, "blur_y": 4.0, "flags": 8 } }]);
+        timeline.get_mut(&2).unwrap().placement[0] = serde_json::from_value(arm)?;
+        let render_list = build(animations)?;
+        let kinds: Vec<_> = render_list
+            .commands()
+            .iter()
+            .map(|command| match command {
+                RenderCommand::Draw(_) => "draw",
+                RenderCommand::PushMask => "push_mask",
+                RenderCommand::ActivateMask => "activate_mask",
+                RenderCommand::DeactivateMask => "deactivate_mask",
+                RenderCommand::PopMask => "pop_mask",
+                RenderCommand::PushFilterGroup { .. } => "push_filters",
+                RenderCommand::PopFilterGroup => "pop_filters",
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "push_mask",
+                "draw",
+                "activate_mask",
+                "push_filters",
+                "draw",
+                "pop_filters",
+                "deactivate_mask",
+                "draw",
+                "pop_mask",
+            ]
+        );
+        // 清除遮罩时重新绘制同一个批次
+        assert_eq!(render_list.commands()[1], render_list.commands()[7]);
+        let RenderCommand::PushFilterGroup { filters, .. } = &render_list.commands()[3] else {
+            unreachable!();
+        };
+        assert_eq!(render_list.filters()[filters.clone()].len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+use std::f32::consts::{PI, TAU};
+
+use glam::Vec2;
+use swf::Twips;
+
+use crate::parser::parse_shape::matrix::Matrix;
+
+/// 根运动的参照
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RootMotionSource {
+    /// 整个根时间轴，以根时间轴上深度最小的实例为参照，通常整个角色就是放在根时间轴上的一个子影片
+    Root,
+    /// 指定实例名、链接名或实例路径的子影片
+    Instance(String),
+}
+
+/// 根运动设置，见[`AnimationPlayer::set_root_motion`](super::AnimationPlayer::set_root_motion)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootMotion {
+    pub source: RootMotionSource,
+    /// 是否同时提取旋转
+    pub rotation: bool,
+}
+
+/// 根运动的变化量，平移为世界坐标中的像素，旋转为弧度
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RootMotionDelta {
+    pub translation: Vec2,
+    pub rotation: f32,
+}
+
+impl std::ops::Add for RootMotionDelta {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            translation: self.translation + rhs.translation,
+            rotation: self.rotation + rhs.rotation,
+        }
+    }
+}
+
+/// 参照实例的位置和朝向
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Pose {
+    x: Twips,
+    y: Twips,
+    angle: f32,
+}
+
+impl From<Matrix> for Pose {
+    fn from(matrix: Matrix) -> Self {
+        Self {
+            x: matrix.tx,
+            y: matrix.ty,
+            angle: matrix.b.atan2(matrix.a),
+        }
+    }
+}
+
+impl Pose {
+    /// 从`self`到`to`的变化量
+    fn delta_to(self, to: Pose, rotation: bool) -> RootMotionDelta {
+        RootMotionDelta {
+            translation: Vec2::new(
+                (to.x - self.x).to_pixels() as f32,
+                (to.y - self.y).to_pixels() as f32,
+            ),
+            rotation: if rotation {
+                normalize_angle(to.angle - self.angle)
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+fn normalize_angle(angle: f32) -> f32 {
+    (angle + PI).rem_euclid(TAU) - PI
+}
+
+/// 根运动的运行状态
+#[derive(Debug)]
+pub(super) struct RootMotionState {
+    pub(super) config: RootMotion,
+    /// 参照在动画首尾的姿态，剥离根运动后参照实例保持在第一帧的位置
+    ends: Option<(Pose, Pose)>,
+    /// 上一次输出时的参照姿态
+    last: Option<Pose>,
+    /// 上一次输出之后跨过的循环次数和方向
+    pending_wraps: u32,
+    wraps_forward: bool,
+    accumulated: RootMotionDelta,
+}
+
+impl RootMotionState {
+    pub(super) fn new(config: RootMotion) -> Self {
+        Self {
+            config,
+            ends: None,
+            last: None,
+            pending_wraps: 0,
+            wraps_forward: true,
+            accumulated: RootMotionDelta::default(),
+        }
+    }
+
+    /// 跳转或切换动画后重新开始计算，不产生位移
+    pub(super) fn reset(&mut self) {
+        self.ends = None;
+        self.last = None;
+        self.pending_wraps = 0;
+    }
+
+    /// 跳转后以新的位置为基准，不产生位移
+    pub(super) fn rebase(&mut self) {
+        self.last = None;
+        self.pending_wraps = 0;
+    }
+
+    pub(super) fn record_wraps(&mut self, wraps: u32, forward: bool) {
+        self.pending_wraps += wraps;
+        self.wraps_forward = forward;
+    }
+
+    pub(super) fn needs_ends(&self) -> bool {
+        self.ends.is_none()
+    }
+
+    /// 设置参照在动画首尾的姿态，用于跨过循环边界时补上首尾之间的位移
+    pub(super) fn set_ends(&mut self, start: Pose, end: Pose) {
+        self.ends = Some((start, end));
+    }
+
+    /// 累加本次输出的根运动，返回需要左乘到所有实例上以剥离根运动的矩阵
+    pub(super) fn advance(&mut self, current: Pose) -> Matrix {
+        let rotation = self.config.rotation;
+        let (start, end) = *self.ends.get_or_insert((current, current));
+        if let Some(last) = self.last {
+            let wraps = std::mem::take(&mut self.pending_wraps);
+            let delta = if wraps == 0 {
+                last.delta_to(current, rotation)
+            } else {
+                let (from, to) = if self.wraps_forward {
+                    (start, end)
+                } else {
+                    (end, start)
+                };
+                let mut delta = last.delta_to(to, rotation);
+                for _ in 1..wraps {
+                    delta = delta + from.delta_to(to, rotation);
+                }
+                delta + from.delta_to(current, rotation)
+            };
+            self.accumulated = self.accumulated + delta;
+        }
+        self.pending_wraps = 0;
+        self.last = Some(current);
+
+        let origin = start;
+        let translate_back = Matrix::translate(origin.x, origin.y);
+        let to_local = Matrix::translate(-current.x, -current.y);
+        if rotation {
+            translate_back * Matrix::rotate(origin.angle - current.angle) * to_local
+        } else {
+            translate_back * to_local
+        }
+    }
+
+    pub(super) fn take(&mut self) -> RootMotionDelta {
+        std::mem::take(&mut self.accumulated)
+    }
+}
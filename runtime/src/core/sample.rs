@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use super::{
+    AnimationLibrary, CollectBuffers, Collector, Inherited, ROOT_INSTANCE_ID, RuntimeInstance,
+    error::RuntimeError,
+};
+
+/// [`sample`]的选项
+#[derive(Debug, Clone, Default)]
+pub struct SampleOptions {
+    /// Key为部位名，Value为皮肤名，没有指定的部位使用默认皮肤
+    pub skins: HashMap<String, String>,
+}
+
+/// 不经过播放器，直接计算动画在指定时间（秒）的实例，超出范围的时间会被限制在动画时长内。
+///
+/// 子影片的播放进度按放置时间从根时间推算，与从头播放到该时间的结果一致。不会修改任何状态，也不触发事件，
+/// 可以用于缩略图生成、时间轴预览和测试。
+pub fn sample(
+    library: &AnimationLibrary,
+    animation: &str,
+    time: f32,
+    options: &SampleOptions,
+) -> Result<Vec<RuntimeInstance>> {
+    let Some(animation) = library.animation(animation) else {
+        return Err(RuntimeError::AnimationNotFound(animation.to_owned()).into());
+    };
+    let mut instances = Vec::new();
+    let mut buffers = CollectBuffers::default();
+    let mut collector = Collector {
+        children_clip: library.children_clip(),
+        active_clip: &mut HashMap::new(),
+        clip_controls: &mut HashMap::new(),
+        overrides: &HashMap::new(),
+        current_skins: &options.skins,
+        frame_rate: library.frame_rate(),
+        elapsed_time: 0.0,
+        active_instances: &mut instances,
+        instance_count: 0,
+        buffers: &mut buffers,
+        events: None,
+        listeners: &HashMap::new(),
+        animation_name: &animation.name,
+        root_motion_source: None,
+        root_motion_reference: None,
+        interpolation: None,
+    };
+    collector.collect(
+        ROOT_INSTANCE_ID,
+        "",
+        &animation.timeline,
+        time.clamp(0.0, animation.duration),
+        Inherited::default(),
+    )?;
+    Ok(instances)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::super::{
+        AnimationPlayer,
+        test::{ids, test_animations},
+    };
+    use super::*;
+
+    #[test]
+    fn matches_playback() -> Result<()> {
+        let library = Arc::new(AnimationLibrary::from(test_animations()));
+        let options = SampleOptions::default();
+        let mut player = AnimationPlayer::with_library(library.clone());
+        player.set_play_animation("default", true, None)?;
+        let mut instances = Vec::new();
+        for frame in 0..10 {
+            player.update(&mut instances, 0.1);
+            let sampled = sample(&library, "default", frame as f32 * 0.1, &options)?;
+            assert_eq!(sampled, instances);
+        }
+        // 子影片在第2帧放置，第5帧时处于其第3帧
+        assert_eq!(
+            ids(&sample(&library, "default", 0.5, &options)?),
+            vec![1, 3]
+        );
+        assert_eq!(ids(&sample(&library, "default", 5.0, &options)?), vec![1]);
+        assert!(sample(&library, "missing", 0.0, &options).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use swf::CharacterId;
+
+use crate::parser::{Animation, MovieClip};
+
+/// 皮肤目录，在创建动画资源时生成一次，查询时不分配内存。
+///
+/// 部位名为皮肤子影片的实例名，没有实例名时为子影片的链接名；
+/// 子影片的链接名本身也是一个部位，作用于它的所有实例。
+#[derive(Debug, Default)]
+pub struct SkinCatalog {
+    /// Key为部位名，Value为可用的皮肤名，已排序
+    parts: BTreeMap<String, Vec<String>>,
+    /// Key为套装名，Value为部位名到皮肤名的映射
+    sets: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl SkinCatalog {
+    pub(super) fn new(
+        animations: &HashMap<String, Animation>,
+        children_clip: &HashMap<CharacterId, MovieClip>,
+    ) -> Self {
+        let mut parts: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        let mut sets: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+        let mut add_part = |part: &str, clip: &MovieClip| {
+            let skins = parts.entry(part.to_owned()).or_default();
+            skins.extend(clip.skin_frames().keys().cloned());
+            for outfit in clip.outfits() {
+                sets.entry(outfit.clone())
+                    .or_default()
+                    .insert(part.to_owned(), outfit.clone());
+            }
+        };
+
+        for clip in children_clip.values().filter(|clip| clip.is_skin_frame()) {
+            add_part(clip.name().expect("替换皮肤的影片剪辑必须命名！"), clip);
+        }
+        // 带实例名的放置可以单独换肤，同一个子影片被放置多次时不会互相影响
+        let placements = animations
+            .values()
+            .map(|animation| &animation.timeline)
+            .chain(children_clip.values().map(MovieClip::timeline))
+            .flat_map(BTreeMap::values)
+            .flat_map(|depth_timeline| depth_timeline.placement.iter());
+        for placement in placements {
+            let (Some(name), Some(id)) = (placement.name(), placement.resource_id()) else {
+                continue;
+            };
+            if let Some(clip) = children_clip.get(&id).filter(|clip| clip.is_skin_frame()) {
+                add_part(name, clip);
+            }
+        }
+
+        Self {
+            parts: parts
+                .into_iter()
+                .map(|(part, skins)| (part, skins.into_iter().collect()))
+                .collect(),
+            sets,
+        }
+    }
+
+    /// 所有可以换肤的部位
+    pub fn parts(&self) -> impl Iterator<Item = &str> {
+        self.parts.keys().map(String::as_str)
+    }
+
+    /// 部位可用的皮肤，部位不存在时返回`None`
+    pub fn skins(&self, part: &str) -> Option<&[String]> {
+        self.parts.get(part).map(Vec::as_slice)
+    }
+
+    pub fn has_part(&self, part: &str) -> bool {
+        self.parts.contains_key(part)
+    }
+
+    pub fn has_skin(&self, part: &str, skin: &str) -> bool {
+        self.skins(part)
+            .is_some_and(|skins| skins.iter().any(|s| s == skin))
+    }
+
+    /// 所有皮肤套装名
+    pub fn skin_sets(&self) -> impl Iterator<Item = &str> {
+        self.sets.keys().map(String::as_str)
+    }
+
+    /// 皮肤套装中部位到皮肤的映射
+    pub fn skin_set(&self, name: &str) -> Option<&BTreeMap<String, String>> {
+        self.sets.get(name)
+    }
+
+    /// 添加或合并一个皮肤套装
+    pub(super) fn insert_skin_set<I, P, S>(&mut self, name: &str, skins: I)
+    where
+        I: IntoIterator<Item = (P, S)>,
+        P: Into<String>,
+        S: Into<String>,
+    {
+        self.sets.entry(name.to_owned()).or_default().extend(
+            skins
+                .into_iter()
+                .map(|(part, skin)| (part.into(), skin.into())),
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use swf::{CharacterId, Fixed8};
+
+use crate::parser::parse_shape::matrix::Matrix;
+
+use super::{
+    FrameTarget, LoopMode, clip::ClipControl, display::DisplaySettings, frame_clock::FrameClock,
+    overrides::InstanceOverride,
+};
+
+/// 播放器状态快照，通过[`AnimationPlayer::snapshot`](super::AnimationPlayer::snapshot)获取，
+/// 可以序列化后用于存档、回滚网络同步和回放。
+///
+/// 快照只包含播放状态，不包含动画资源、回调和事件队列。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerState {
+    pub(super) animation: Option<String>,
+    pub(super) time: f32,
+    pub(super) loop_mode: LoopMode,
+    pub(super) completed_loops: u32,
+    pub(super) reversed: bool,
+    pub(super) speed: f32,
+    pub(super) playing: bool,
+    pub(super) skins: BTreeMap<String, String>,
+    /// 子影片实例的播放进度，按实例id排序
+    pub(super) clips: Vec<ClipState>,
+    pub(super) clip_controls: BTreeMap<String, ClipControlState>,
+    pub(super) overrides: BTreeMap<String, OverrideState>,
+    /// 逐帧播放的时钟，为`None`时按连续时间播放
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) frame_clock: Option<FrameClockState>,
+    /// 逐帧播放时是否在帧之间插值
+    #[serde(default)]
+    pub(super) frame_interpolation: bool,
+    #[serde(default)]
+    pub(super) display: DisplayState,
+}
+
+impl PlayerState {
+    /// 快照中的动画名
+    pub fn animation(&self) -> Option<&str> {
+        self.animation.as_deref()
+    }
+
+    /// 快照中的当前时间（秒）
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+}
+
+/// 单个子影片实例的播放进度
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct ClipState {
+    pub(super) instance_id: u64,
+    pub(super) time: f32,
+    pub(super) placement_start: f32,
+    /// 被控制停止时固定的时间
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) pinned_time: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct ClipControlState {
+    playing: bool,
+    speed: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    goto: Option<ClipTarget>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ClipTarget {
+    Frame(u32),
+    Label(String),
+}
+
+impl From<&ClipControl> for ClipControlState {
+    fn from(control: &ClipControl) -> Self {
+        Self {
+            playing: control.playing,
+            speed: control.speed,
+            goto: control.goto.as_ref().map(|target| match target {
+                FrameTarget::Frame(frame) => ClipTarget::Frame(*frame),
+                FrameTarget::Label(label) => ClipTarget::Label(label.clone()),
+            }),
+        }
+    }
+}
+
+impl From<&ClipControlState> for ClipControl {
+    fn from(state: &ClipControlState) -> Self {
+        Self {
+            playing: state.playing,
+            speed: state.speed,
+            goto: state.goto.as_ref().map(|target| match target {
+                ClipTarget::Frame(frame) => FrameTarget::Frame(*frame),
+                ClipTarget::Label(label) => FrameTarget::Label(label.clone()),
+            }),
+            goto_applied: false,
+        }
+    }
+}
+
+/// 逐帧播放的时钟，包括还不足一帧的累积时间
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(super) struct FrameClockState {
+    accumulator: f32,
+    interpolate: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    interpolation: Option<f32>,
+}
+
+impl From<&FrameClock> for FrameClockState {
+    fn from(clock: &FrameClock) -> Self {
+        Self {
+            accumulator: clock.accumulator,
+            interpolate: clock.interpolate,
+            interpolation: clock.interpolation,
+        }
+    }
+}
+
+impl From<&FrameClockState> for FrameClock {
+    fn from(state: &FrameClockState) -> Self {
+        Self {
+            accumulator: state.accumulator,
+            interpolate: state.interpolate,
+            interpolation: state.interpolation,
+        }
+    }
+}
+
+/// 作用于整个播放器的变换、翻转和颜色
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct DisplayState {
+    root_transform: Matrix,
+    flip_x: bool,
+    flip_y: bool,
+    color_transform: ColorTransformState,
+    opacity: f32,
+}
+
+impl Default for DisplayState {
+    fn default() -> Self {
+        (&DisplaySettings::default()).into()
+    }
+}
+
+impl From<&DisplaySettings> for DisplayState {
+    fn from(display: &DisplaySettings) -> Self {
+        Self {
+            root_transform: display.root_transform,
+            flip_x: display.flip_x,
+            flip_y: display.flip_y,
+            color_transform: (&display.color_transform).into(),
+            opacity: display.opacity,
+        }
+    }
+}
+
+impl From<&DisplayState> for DisplaySettings {
+    fn from(state: &DisplayState) -> Self {
+        Self {
+            root_transform: state.root_transform,
+            flip_x: state.flip_x,
+            flip_y: state.flip_y,
+            color_transform: (&state.color_transform).into(),
+            opacity: state.opacity,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct OverrideState {
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    hidden: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    color_transform: Option<ColorTransformState>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    character: Option<CharacterId>,
+}
+
+/// 颜色变换，乘数保存为8.8定点数的原始值，保证恢复后完全一致
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct ColorTransformState {
+    multiply: [i16; 4],
+    add: [i16; 4],
+}
+
+impl From<&InstanceOverride> for OverrideState {
+    fn from(instance_override: &InstanceOverride) -> Self {
+        Self {
+            hidden: instance_override.hidden,
+            color_transform: instance_override.color_transform.as_ref().map(Into::into),
+            character: instance_override.character,
+        }
+    }
+}
+
+impl From<&OverrideState> for InstanceOverride {
+    fn from(state: &OverrideState) -> Self {
+        Self {
+            hidden: state.hidden,
+            color_transform: state.color_transform.as_ref().map(Into::into),
+            character: state.character,
+        }
+    }
+}
+
+impl From<&swf::ColorTransform> for ColorTransformState {
+    fn from(color: &swf::ColorTransform) -> Self {
+        Self {
+            multiply: [
+                color.r_multiply.get(),
+                color.g_multiply.get(),
+                color.b_multiply.get(),
+                color.a_multiply.get(),
+            ],
+            add: [color.r_add, color.g_add, color.b_add, color.a_add],
+        }
+    }
+}
+
+impl From<&ColorTransformState> for swf::ColorTransform {
+    fn from(color: &ColorTransformState) -> Self {
+        Self {
+            r_multiply: Fixed8::from_bits(color.multiply[0]),
+            g_multiply: Fixed8::from_bits(color.multiply[1]),
+            b_multiply: Fixed8::from_bits(color.multiply[2]),
+            a_multiply: Fixed8::from_bits(color.multiply[3]),
+            r_add: color.add[0],
+            g_add: color.add[1],
+            b_add: color.add[2],
+            a_add: color.add[3],
+        }
+    }
+}
@@ -1 +1,592 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
 
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use swf::Fixed8;
+
+use super::{
+    AnimationLibrary, AnimationPlayer, LoopMode, RuntimeInstance, error::RuntimeError,
+    stable_instance_id,
+};
+
+/// 参数定义
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ParameterDefinition {
+    Bool {
+        #[serde(default)]
+        default: bool,
+    },
+    Float {
+        #[serde(default)]
+        default: f32,
+    },
+    /// 触发器，被一次切换使用后自动复位
+    Trigger,
+}
+
+impl ParameterDefinition {
+    fn initial_value(&self) -> ParameterValue {
+        match *self {
+            ParameterDefinition::Bool { default } => ParameterValue::Bool(default),
+            ParameterDefinition::Float { default } => ParameterValue::Float(default),
+            ParameterDefinition::Trigger => ParameterValue::Trigger(false),
+        }
+    }
+}
+
+/// 参数的当前值
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParameterValue {
+    Bool(bool),
+    Float(f32),
+    /// 是否已触发
+    Trigger(bool),
+}
+
+/// 切换条件
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Condition {
+    /// 布尔参数为真
+    If { parameter: String },
+    /// 布尔参数为假
+    IfNot { parameter: String },
+    /// 浮点参数大于`value`
+    Greater { parameter: String, value: f32 },
+    /// 浮点参数小于`value`
+    Less { parameter: String, value: f32 },
+    /// 触发器已触发
+    Triggered { parameter: String },
+}
+
+impl Condition {
+    pub fn parameter(&self) -> &str {
+        match self {
+            Condition::If { parameter }
+            | Condition::IfNot { parameter }
+            | Condition::Greater { parameter, .. }
+            | Condition::Less { parameter, .. }
+            | Condition::Triggered { parameter } => parameter,
+        }
+    }
+
+    /// 条件是否可以作用于该类型的参数
+    fn accepts(&self, definition: &ParameterDefinition) -> bool {
+        matches!(
+            (self, definition),
+            (
+                Condition::If { .. } | Condition::IfNot { .. },
+                ParameterDefinition::Bool { .. }
+            ) | (
+                Condition::Greater { .. } | Condition::Less { .. },
+                ParameterDefinition::Float { .. }
+            ) | (Condition::Triggered { .. }, ParameterDefinition::Trigger)
+        )
+    }
+
+    fn is_met(&self, value: Option<&ParameterValue>) -> bool {
+        match (self, value) {
+            (Condition::If { .. }, Some(ParameterValue::Bool(value))) => *value,
+            (Condition::IfNot { .. }, Some(ParameterValue::Bool(value))) => !*value,
+            (Condition::Greater { value, .. }, Some(ParameterValue::Float(current))) => {
+                current > value
+            }
+            (Condition::Less { value, .. }, Some(ParameterValue::Float(current))) => {
+                current < value
+            }
+            (Condition::Triggered { .. }, Some(ParameterValue::Trigger(triggered))) => *triggered,
+            _ => false,
+        }
+    }
+}
+
+/// 状态切换
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransitionDefinition {
+    /// 目标状态名
+    pub to: String,
+    /// 所有条件都满足时才切换
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    /// 归一化的退出时间，进入状态后播放到动画时长的这个比例之后才能切换，`1.0`为播放完一遍；
+    /// 为空时随时可以切换
+    #[serde(default)]
+    pub exit_time: Option<f32>,
+    /// 交叉淡化时长（秒），为0时直接切换
+    #[serde(default)]
+    pub duration: f32,
+    /// 只对任意状态切换有效，是否允许切换到当前所在的状态
+    #[serde(default)]
+    pub can_transition_to_self: bool,
+}
+
+/// 状态，每个状态播放一个动画
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateDefinition {
+    pub name: String,
+    /// 动画名
+    pub animation: String,
+    #[serde(default = "default_loop_mode")]
+    pub loop_mode: LoopMode,
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+    /// 从这个状态出发的切换，按顺序检查
+    #[serde(default)]
+    pub transitions: Vec<TransitionDefinition>,
+}
+
+fn default_loop_mode() -> LoopMode {
+    LoopMode::Loop
+}
+
+fn default_speed() -> f32 {
+    1.0
+}
+
+/// 状态机定义，可以和转换后的动画放在一起，由JSON或TOML加载
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateMachineDefinition {
+    /// 初始状态，为空时使用第一个状态
+    #[serde(default)]
+    pub initial_state: Option<String>,
+    #[serde(default)]
+    pub parameters: BTreeMap<String, ParameterDefinition>,
+    pub states: Vec<StateDefinition>,
+    /// 任意状态出发的切换，优先于当前状态的切换检查
+    #[serde(default)]
+    pub any_state: Vec<TransitionDefinition>,
+}
+
+impl StateMachineDefinition {
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    fn state_index(&self, name: &str) -> Option<usize> {
+        self.states.iter().position(|state| state.name == name)
+    }
+
+    /// 检查引用的状态、动画和参数是否都存在，以及条件和参数的类型是否匹配
+    pub fn validate(&self, library: &AnimationLibrary) -> Result<()> {
+        if self.states.is_empty() {
+            return Err(RuntimeError::StateNotFound(
+                self.initial_state.clone().unwrap_or_default(),
+            )
+            .into());
+        }
+        if let Some(initial_state) = &self.initial_state
+            && self.state_index(initial_state).is_none()
+        {
+            return Err(RuntimeError::StateNotFound(initial_state.clone()).into());
+        }
+        for state in &self.states {
+            if library.animation(&state.animation).is_none() {
+                return Err(RuntimeError::AnimationNotFound(state.animation.clone()).into());
+            }
+        }
+        let transitions = self
+            .states
+            .iter()
+            .flat_map(|state| &state.transitions)
+            .chain(&self.any_state);
+        for transition in transitions {
+            if self.state_index(&transition.to).is_none() {
+                return Err(RuntimeError::StateNotFound(transition.to.clone()).into());
+            }
+            for condition in &transition.conditions {
+                let parameter = condition.parameter();
+                let Some(definition) = self.parameters.get(parameter) else {
+                    return Err(RuntimeError::ParameterNotFound(parameter.to_owned()).into());
+                };
+                if !condition.accepts(definition) {
+                    return Err(RuntimeError::ParameterTypeMismatch(parameter.to_owned()).into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 正在进行的交叉淡化
+#[derive(Debug, Clone, Copy)]
+struct Crossfade {
+    duration: f32,
+    elapsed: f32,
+}
+
+/// 数据驱动的动画状态机，根据参数在状态之间切换，切换时可以交叉淡化。
+///
+/// 帧事件、完成回调等只由当前状态的播放器触发，可以通过[`Self::player_mut`]注册。
+/// 交叉淡化期间上一个状态继续播放并逐渐透明，它的实例排在完全不透明的当前状态之后输出，
+/// 覆盖在当前状态上面，实例id与当前状态的不会重复。
+pub struct StateMachine {
+    definition: Arc<StateMachineDefinition>,
+    parameters: HashMap<String, ParameterValue>,
+    current_state: usize,
+    /// 进入当前状态后经过的播放时间（秒）
+    state_time: f32,
+    player: AnimationPlayer,
+    /// 交叉淡化时播放上一个状态
+    fade_player: AnimationPlayer,
+    crossfade: Option<Crossfade>,
+    instances: Vec<RuntimeInstance>,
+    fade_instances: Vec<RuntimeInstance>,
+}
+
+impl StateMachine {
+    pub fn new(
+        library: Arc<AnimationLibrary>,
+        definition: impl Into<Arc<StateMachineDefinition>>,
+    ) -> Result<Self> {
+        let definition = definition.into();
+        definition.validate(&library)?;
+        let parameters = definition
+            .parameters
+            .iter()
+            .map(|(name, parameter)| (name.clone(), parameter.initial_value()))
+            .collect();
+        let current_state = definition
+            .initial_state
+            .as_deref()
+            .and_then(|name| definition.state_index(name))
+            .unwrap_or_default();
+        let mut state_machine = Self {
+            definition,
+            parameters,
+            current_state,
+            state_time: 0.0,
+            player: AnimationPlayer::with_library(library.clone()),
+            fade_player: AnimationPlayer::with_library(library),
+            crossfade: None,
+            instances: Vec::new(),
+            fade_instances: Vec::new(),
+        };
+        state_machine.enter_state(current_state, 0.0)?;
+        Ok(state_machine)
+    }
+
+    pub fn definition(&self) -> &Arc<StateMachineDefinition> {
+        &self.definition
+    }
+
+    /// 当前状态的播放器
+    pub fn player(&self) -> &AnimationPlayer {
+        &self.player
+    }
+
+    pub fn player_mut(&mut self) -> &mut AnimationPlayer {
+        &mut self.player
+    }
+
+    pub fn current_state(&self) -> &str {
+        &self.definition.states[self.current_state].name
+    }
+
+    /// 是否处于交叉淡化中
+    pub fn in_transition(&self) -> bool {
+        self.crossfade.is_some()
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<ParameterValue> {
+        self.parameters.get(name).copied()
+    }
+
+    pub fn set_bool(&mut self, name: &str, value: bool) -> Result<()> {
+        self.set_parameter(name, ParameterValue::Bool(value))
+    }
+
+    pub fn set_float(&mut self, name: &str, value: f32) -> Result<()> {
+        self.set_parameter(name, ParameterValue::Float(value))
+    }
+
+    /// 触发触发器，直到被一次切换使用或调用[`Self::reset_trigger`]
+    pub fn set_trigger(&mut self, name: &str) -> Result<()> {
+        self.set_parameter(name, ParameterValue::Trigger(true))
+    }
+
+    pub fn reset_trigger(&mut self, name: &str) -> Result<()> {
+        self.set_parameter(name, ParameterValue::Trigger(false))
+    }
+
+    fn set_parameter(&mut self, name: &str, value: ParameterValue) -> Result<()> {
+        let Some(current) = self.parameters.get_mut(name) else {
+            return Err(RuntimeError::ParameterNotFound(name.to_owned()).into());
+        };
+        if std::mem::discriminant(current) != std::mem::discriminant(&value) {
+            return Err(RuntimeError::ParameterTypeMismatch(name.to_owned()).into());
+        }
+        *current = value;
+        Ok(())
+    }
+
+    /// 不检查条件直接切换到指定状态
+    pub fn play(&mut self, state: &str, crossfade: f32) -> Result<()> {
+        let Some(index) = self.definition.state_index(state) else {
+            return Err(RuntimeError::StateNotFound(state.to_owned()).into());
+        };
+        self.enter_state(index, crossfade)
+    }
+
+    /// 检查切换条件并推进播放，输出当前状态（以及淡出中的上一个状态）的实例
+    pub fn update(&mut self, active_instances: &mut Vec<RuntimeInstance>, delta_time: f32) {
+        let definition = self.definition.clone();
+        if let Some(transition) = self.find_transition(&definition) {
+            for condition in &transition.conditions {
+                if let Condition::Triggered { parameter } = condition {
+                    self.parameters
+                        .insert(parameter.clone(), ParameterValue::Trigger(false));
+                }
+            }
+            let index = definition.state_index(&transition.to).unwrap();
+            // 定义已经检查过，动画一定存在
+            self.enter_state(index, transition.duration).unwrap();
+        }
+
+        self.player.update(&mut self.instances, delta_time);
+        self.state_time += (delta_time * self.player.speed()).abs();
+
+        active_instances.clear();
+        if let Some(crossfade) = self.crossfade.as_mut() {
+            crossfade.elapsed += delta_time.abs();
+            if crossfade.elapsed >= crossfade.duration {
+                self.crossfade = None;
+            }
+        }
+        if let Some(crossfade) = self.crossfade {
+            let weight = crossfade.elapsed / crossfade.duration;
+            // 显示设置通过`player_mut`修改，淡出的状态同样跟随
+            self.fade_player.display.clone_from(&self.player.display);
+            self.fade_player
+                .update(&mut self.fade_instances, delta_time);
+            // 只淡出上一个状态，两层同时半透明时不透明的部分在淡化中途也会变暗
+            active_instances.extend_from_slice(&self.instances);
+            active_instances.extend(self.fade_instances.iter().map(|instance| {
+                let mut instance = fade_instance(instance, 1.0 - weight);
+                instance.instance_id = stable_instance_id(instance.instance_id, 0, 0, 0);
+                instance
+            }));
+            for (draw_order, instance) in active_instances.iter_mut().enumerate() {
+                instance.draw_order = draw_order as u32;
+            }
+        } else {
+            active_instances.extend_from_slice(&self.instances);
+        }
+    }
+
+    /// 找到第一个满足条件的切换，任意状态的切换优先
+    fn find_transition<'d>(
+        &self,
+        definition: &'d StateMachineDefinition,
+    ) -> Option<&'d TransitionDefinition> {
+        let state = &definition.states[self.current_state];
+        let duration = self.player.current_animation()?.duration;
+        definition
+            .any_state
+            .iter()
+            .chain(&state.transitions)
+            .enumerate()
+            .find(|(index, transition)| {
+                let from_any_state = *index < definition.any_state.len();
+                if from_any_state
+                    && transition.to == state.name
+                    && !transition.can_transition_to_self
+                {
+                    return false;
+                }
+                if let Some(exit_time) = transition.exit_time
+                    && self.state_time < exit_time * duration
+                {
+                    return false;
+                }
+                transition
+                    .conditions
+                    .iter()
+                    .all(|condition| condition.is_met(self.parameters.get(condition.parameter())))
+            })
+            .map(|(_, transition)| transition)
+    }
+
+    fn enter_state(&mut self, index: usize, crossfade: f32) -> Result<()> {
+        let state = &self.definition.states[index];
+        if crossfade > 0.0 && !self.instances.is_empty() {
+            // 上一个状态从当前进度继续播放，直到淡出
+            let previous = &self.definition.states[self.current_state];
+            self.fade_player
+                .set_play_animation(&previous.animation, previous.loop_mode, None)?;
+            self.fade_player.set_speed(previous.speed);
+            self.fade_player
+                .current_skins
+                .clone_from(&self.player.current_skins);
+            self.fade_player
+                .overrides
+                .clone_from(&self.player.overrides);
+            self.fade_player
+                .clip_controls
+                .clone_from(&self.player.clip_controls);
+            self.fade_player.seek(self.player.current_time());
+            self.fade_player.set_playing(true);
+            self.crossfade = Some(Crossfade {
+                duration: crossfade,
+                elapsed: 0.0,
+            });
+        } else {
+            self.crossfade = None;
+        }
+        self.player.set_speed(state.speed);
+        self.player
+            .set_play_animation(&state.animation, state.loop_mode, None)?;
+        // 上一个状态播放完成时播放器已经停止
+        self.player.set_playing(true);
+        self.current_state = index;
+        self.state_time = 0.0;
+        Ok(())
+    }
+}
+
+/// 按权重缩放实例的透明度
+fn fade_instance(instance: &RuntimeInstance, weight: f32) -> RuntimeInstance {
+    let mut instance = instance.clone();
+    let alpha = instance.color_transform.a_multiply.to_f32() * weight;
+    instance.color_transform.a_multiply = Fixed8::from_f32(alpha);
+    instance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test::{ids, test_animations};
+    use super::*;
+
+    #[test]
+    fn transitions() -> Result<()> {
+        let definition = StateMachineDefinition::from_toml(
+            r#"
+            [parameters]
+            run = { type = "bool" }
+            jump = { type = "trigger" }
+
+            [[states]]
+            name = "idle"
+            animation = "default"
+            transitions = [{ to = "run", conditions = [{ op = "if", parameter = "run" }], duration = 0.2 }]
+
+            [[states]]
+            name = "run"
+            animation = "default"
+            speed = 2.0
+
+            [[states]]
+            name = "jump"
+            animation = "default"
+            loop_mode = "once"
+            transitions = [{ to = "idle", exit_time = 1.0 }]
+
+            [[any_state]]
+            to = "jump"
+            conditions = [{ op = "triggered", parameter = "jump" }]
+            "#,
+        )?;
+        let library = Arc::new(AnimationLibrary::from(test_animations()));
+        let mut state_machine = StateMachine::new(library.clone(), definition.clone())?;
+        let mut instances = Vec::new();
+        assert_eq!(state_machine.current_state(), "idle");
+
+        state_machine.update(&mut instances, 0.1);
+        assert!(state_machine.set_float("run", 1.0).is_err());
+        state_machine.set_bool("run", true)?;
+        state_machine.update(&mut instances, 0.1);
+        assert_eq!(state_machine.current_state(), "run");
+        assert!(state_machine.in_transition());
+        // 淡出的上一个状态排在后面，覆盖在不透明的当前状态上
+        assert_eq!(ids(&instances), vec![1, 1]);
+        assert_eq!(instances[0].color_transform().a_multiply.to_f32(), 1.0);
+        let fade_alpha = instances[1].color_transform().a_multiply.to_f32();
+        assert!(fade_alpha > 0.0 && fade_alpha < 1.0);
+        assert_ne!(instances[0].instance_id(), instances[1].instance_id());
+        assert_eq!(state_machine.player().speed(), 2.0);
+        state_machine.update(&mut instances, 0.2);
+        assert!(!state_machine.in_transition());
+        // 两倍速播放到第2帧，子影片已经放置
+        assert_eq!(ids(&instances), vec![1, 2]);
+
+        // 任意状态的切换优先，触发器被使用后复位
+        state_machine.set_trigger("jump")?;
+        state_machine.update(&mut instances, 0.1);
+        assert_eq!(state_machine.current_state(), "jump");
+        assert_eq!(
+            state_machine.parameter("jump"),
+            Some(ParameterValue::Trigger(false))
+        );
+        // 播放完一遍之后才能离开
+        for _ in 0..9 {
+            state_machine.update(&mut instances, 0.1);
+        }
+        assert_eq!(state_machine.current_state(), "jump");
+        state_machine.update(&mut instances, 0.1);
+        assert_eq!(state_machine.current_state(), "idle");
+        // 从播放完成的状态切换出来后继续播放
+        assert_eq!(state_machine.player().current_frame(), 1);
+        state_machine.set_bool("run", false)?;
+        state_machine.update(&mut instances, 0.1);
+        state_machine.update(&mut instances, 0.1);
+        assert_eq!(state_machine.player().current_frame(), 3);
+
+        let json = serde_json::to_string(&definition)?;
+        assert_eq!(StateMachineDefinition::from_json(&json)?, definition);
+        let mut invalid = definition;
+        invalid.states[0].transitions[0].conditions = vec![Condition::Greater {
+            parameter: "run".to_owned(),
+            value: 0.0,
+        }];
+        assert!(StateMachine::new(library, invalid).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn crossfade_follows_player_settings() -> Result<()> {
+        let definition = StateMachineDefinition::from_toml(
+            r#"
+            [parameters]
+            run = { type = "bool" }
+
+            [[states]]
+            name = "idle"
+            animation = "default"
+            transitions = [{ to = "run", conditions = [{ op = "if", parameter = "run" }], duration = 0.5 }]
+
+            [[states]]
+            name = "run"
+            animation = "default"
+            "#,
+        )?;
+        let library = Arc::new(AnimationLibrary::from(test_animations()));
+        let mut state_machine = StateMachine::new(library, definition)?;
+        let mut instances = Vec::new();
+        state_machine.update(&mut instances, 0.1);
+        // 子影片的播放控制同样作用于淡出的状态
+        state_machine.player_mut().clip_mut("arm")?.goto_and_stop(2);
+        state_machine.set_bool("run", true)?;
+        state_machine.update(&mut instances, 0.1);
+        assert!(state_machine.in_transition());
+
+        // 淡出中翻转，两个状态的输出都被翻转
+        state_machine.player_mut().set_flip_x(true);
+        state_machine.update(&mut instances, 0.1);
+        assert!(state_machine.in_transition());
+        // 淡出的状态已经播放到放置子影片的帧，子影片停在第2帧
+        assert_eq!(ids(&instances), vec![1, 1, 3]);
+        assert!(
+            instances
+                .iter()
+                .all(|instance| instance.transform_matrix().a == -1.0)
+        );
+        Ok(())
+    }
+}
@@ -11,10 +11,15 @@ use bitmap::CompressedBitmap;
 use decode::decode_define_bits_jpeg_dimensions;
 use parse_shape::matrix::Matrix;
 use serde::{Deserialize, Serialize};
-use swf::{CharacterId, DefineBitsLossless, Depth, Encoding, PlaceObject, Shape, SwfStr, Tag};
+use swf::{
+    CharacterId, DefineBitsLossless, Depth, Encoding, PlaceObject, Rectangle, Shape, SwfStr, Tag,
+    Twips,
+};
 use swf_derive::KeyFrame;
 use types::{BlendMode, Filter};
 
+use crate::core::{AnimationLibrary, BakedAnimations};
+
 pub mod bitmap;
 mod decode;
 pub mod parse_shape;
@@ -115,6 +120,15 @@ pub struct Placement {
     blend_mode: BlendMode,
     color_transform: ColorTransform,
     filters: Vec<Filter>,
+    /// 实例名，同一个资源被多次放置时各自的名字
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    /// 该关键帧是否创建了新的实例（Place或Replace），子影片会从第一帧重新播放
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    new_instance: bool,
+    /// 不为空时该实例是遮罩，遮住深度在它之上直到`clip_depth`（包括）的实例
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clip_depth: Option<Depth>,
 }
 
 impl Placement {
@@ -122,6 +136,18 @@ impl Placement {
         self.resource_id
     }
 
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn is_new_instance(&self) -> bool {
+        self.new_instance
+    }
+
+    pub fn clip_depth(&self) -> Option<Depth> {
+        self.clip_depth
+    }
+
     pub fn blend_mode(&self) -> BlendMode {
         self.blend_mode
     }
@@ -164,9 +190,41 @@ impl DepthTimeline {
 pub struct Event {
     pub time: f32,
     pub name: String,
+    /// 事件附带的数据，标签`event_hit:10`中`:`后面的部分
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
 }
 
 impl Event {
+    /// 从去掉`event_`前缀的标签解析事件
+    fn from_label(time: f32, label: &str) -> Self {
+        let (name, payload) = match label.split_once(':') {
+            Some((name, payload)) => (name, Some(payload.to_owned())),
+            None => (label, None),
+        };
+        Self {
+            time,
+            name: name.to_owned(),
+            payload,
+        }
+    }
+}
+
+/// 时间轴上开始播放的音效，音效资源本身不做解析，引擎根据id自行加载
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct SoundEvent {
+    pub time: f32,
+    pub id: CharacterId,
+}
+
+/// 普通帧标签，不带`anim_`、`event_`、`skin_`前缀，用于运行时跳转
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct Label {
+    pub time: f32,
+    pub name: String,
+}
+
+impl Label {
     fn new(time: f32, name: String) -> Self {
         Self { time, name }
     }
@@ -179,6 +237,13 @@ pub struct Animation {
     pub timeline: BTreeMap<Depth, DepthTimeline>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub events: Vec<Event>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<Label>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sounds: Vec<SoundEvent>,
+    /// 所有帧的包围盒并集，由运行时在提供图形包围盒后计算
+    #[serde(skip)]
+    max_bounds: Option<Rectangle<Twips>>,
 }
 impl Animation {
     fn new(name: String) -> Self {
@@ -187,6 +252,16 @@ impl Animation {
             ..Default::default()
         }
     }
+
+    /// 所有帧（包括子影片的所有皮肤）的包围盒并集，包含滤镜的扩展，
+    /// 可用于界面布局和屏幕外剔除。没有提供图形包围盒时为`None`
+    pub fn max_bounds(&self) -> Option<&Rectangle<Twips>> {
+        self.max_bounds.as_ref()
+    }
+
+    pub(crate) fn set_max_bounds(&mut self, max_bounds: Option<Rectangle<Twips>>) {
+        self.max_bounds = max_bounds;
+    }
 }
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
@@ -199,8 +274,13 @@ pub struct MovieClip {
     skin_frames: HashMap<String, u32>,
     #[serde(skip_serializing_if = "String::is_empty")]
     default_skin: String,
-    #[serde(skip_serializing)]
-    pub current_time: f32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    events: Vec<Event>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    labels: Vec<Label>,
+    /// 通过`outfit_`标签声明的套装，同名的皮肤帧属于这个套装
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    outfits: Vec<String>,
 }
 impl MovieClip {
     fn new(id: CharacterId, duration: f32) -> Self {
@@ -215,6 +295,10 @@ impl MovieClip {
         self.name.as_deref()
     }
 
+    pub fn id(&self) -> CharacterId {
+        self.id
+    }
+
     pub fn timeline(&self) -> &BTreeMap<Depth, DepthTimeline> {
         &self.timeline
     }
@@ -241,6 +325,18 @@ impl MovieClip {
     pub fn skin_frames(&self) -> &HashMap<String, u32> {
         &self.skin_frames
     }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    pub fn outfits(&self) -> &[String] {
+        &self.outfits
+    }
 }
 
 /// 新格式动画数据
@@ -252,6 +348,9 @@ pub struct Animations {
     pub children_clip: HashMap<CharacterId, MovieClip>,
     /// Key为动画名称，Value为动画数据
     pub animations: HashMap<String, Animation>,
+    /// 皮肤套装，Key为套装名，Value为部位名到皮肤名的映射
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub skin_sets: HashMap<String, HashMap<String, String>>,
 }
 
 impl Animations {
@@ -261,6 +360,13 @@ impl Animations {
             ..Default::default()
         }
     }
+
+    /// 以`fps`预先计算所有动画每一帧的实例，用于[`BakedPlayer`](crate::core::BakedPlayer)。
+    ///
+    /// 会复制一份动画数据来创建资源，已经有[`AnimationLibrary`]时使用[`BakedAnimations::bake`]
+    pub fn bake(&self, fps: f32) -> BakedAnimations {
+        BakedAnimations::bake(&AnimationLibrary::from(self.clone()), fps)
+    }
 }
 
 /// 解析flash动画为新格式，方便集成到游戏引擎中
@@ -356,7 +462,7 @@ fn parse_sprite_animation(
             }
             Tag::FrameLabel(frame_label) => {
                 let label = frame_label.label.to_string_lossy(swf_encoding);
-                parse_sprite_label(label, current_frame, &mut movie_clip);
+                parse_sprite_label(label, current_frame, frame_rate, &mut movie_clip);
             }
             _ => {}
         }
@@ -365,13 +471,35 @@ fn parse_sprite_animation(
 }
 
 // 皮肤定义clip，将每一帧作为一个皮肤资源处理
-fn parse_sprite_label(label: String, current_frame: u32, movie_clip: &mut MovieClip) {
+fn parse_sprite_label(
+    label: String,
+    current_frame: u32,
+    frame_rate: f32,
+    movie_clip: &mut MovieClip,
+) {
     if label.starts_with("skin_") {
         let label = label.trim_start_matches("skin_").to_owned();
         if current_frame == 0 {
             movie_clip.default_skin = label.clone();
         }
         movie_clip.skin_frames.insert(label, current_frame);
+    } else if let Some(outfit) = label.strip_prefix("outfit_") {
+        // 套装标签同时也是这个部位的一个皮肤
+        if current_frame == 0 && movie_clip.default_skin.is_empty() {
+            movie_clip.default_skin = outfit.to_owned();
+        }
+        movie_clip
+            .skin_frames
+            .insert(outfit.to_owned(), current_frame);
+        movie_clip.outfits.push(outfit.to_owned());
+    } else if let Some(event_label) = label.strip_prefix("event_") {
+        // 子影片中的事件，运行时会带上实例路径
+        let time = current_frame as f32 / frame_rate;
+        movie_clip.events.push(Event::from_label(time, event_label));
+    } else {
+        // 普通标签，供运行时控制子影片跳转
+        let time = current_frame as f32 / frame_rate;
+        movie_clip.labels.push(Label::new(time, label));
     }
 }
 
@@ -451,6 +579,19 @@ fn parse_animation_data(
             Tag::ShowFrame => {
                 current_frame += 1;
             }
+            // 停止音效的指令不需要通知引擎播放
+            Tag::StartSound(start_sound)
+                if start_sound.sound_info.event != swf::SoundEvent::Stop =>
+            {
+                let animation = animations
+                    .animations
+                    .entry(current_animation_name.clone())
+                    .or_insert(Animation::new(current_animation_name.clone()));
+                animation.sounds.push(SoundEvent {
+                    time,
+                    id: start_sound.id,
+                });
+            }
             Tag::FrameLabel(frame_label) => {
                 let label = frame_label.label.to_string_lossy(swf_encoding);
                 parse_label(
@@ -499,7 +640,14 @@ fn parse_place_object(
                 }
             }
             let mut placement = Placement::new(time, Some(id));
-            apply_place_object(depth_timeline, &mut placement, place_object, time);
+            placement.new_instance = true;
+            apply_place_object(
+                depth_timeline,
+                &mut placement,
+                place_object,
+                time,
+                swf_encoding,
+            );
             depth_timeline.placement.push(placement);
         }
         swf::PlaceObjectAction::Modify => {
@@ -507,7 +655,14 @@ fn parse_place_object(
             if let Some(depth_timeline) = timeline.get_mut(&place_object.depth) {
                 let mut placement = depth_timeline.placement.last_mut().unwrap().clone();
                 placement.time = time;
-                apply_place_object(depth_timeline, &mut placement, place_object, time);
+                placement.new_instance = false;
+                apply_place_object(
+                    depth_timeline,
+                    &mut placement,
+                    place_object,
+                    time,
+                    swf_encoding,
+                );
                 depth_timeline.placement.push(placement);
             }
         }
@@ -516,7 +671,14 @@ fn parse_place_object(
                 let mut placement = depth_timeline.placement.last_mut().unwrap().clone();
                 placement.time = time;
                 placement.resource_id = Some(id);
-                apply_place_object(depth_timeline, &mut placement, place_object, time);
+                placement.new_instance = true;
+                apply_place_object(
+                    depth_timeline,
+                    &mut placement,
+                    place_object,
+                    time,
+                    swf_encoding,
+                );
                 depth_timeline.placement.push(placement);
             }
         }
@@ -560,10 +722,14 @@ fn parse_label(
         let animation = animations
             .entry(current_animation_name.clone())
             .or_insert(Animation::new(current_animation_name.to_owned()));
-        let event_name = label.trim_start_matches("event_");
-        animation
-            .events
-            .push(Event::new(time, event_name.to_owned()));
+        let event_label = label.trim_start_matches("event_");
+        animation.events.push(Event::from_label(time, event_label));
+    } else if !label.starts_with("anim_") {
+        // 普通标签，记录下来供运行时跳转
+        let animation = animations
+            .entry(current_animation_name.clone())
+            .or_insert(Animation::new(current_animation_name.to_owned()));
+        animation.labels.push(Label::new(time, label.to_owned()));
     }
     // 这里可以添加更多的解析逻辑
 }
@@ -580,7 +746,14 @@ fn apply_place_object(
     placement: &mut Placement,
     place_object: &PlaceObject,
     current_time: f32,
+    swf_encoding: &'static Encoding,
 ) {
+    if let Some(name) = place_object.name {
+        placement.name = Some(name.to_string_lossy(swf_encoding));
+    }
+    if let Some(clip_depth) = place_object.clip_depth {
+        placement.clip_depth = Some(clip_depth);
+    }
     if let Some(matrix) = place_object.matrix {
         depth_timeline.transforms.push(Transform::new(
             current_time,
@@ -0,0 +1,166 @@
+//! 播放稳定后的更新不应分配内存。
+//!
+//! 计数分配器会替换整个测试程序的全局分配器，因此单独放在这个集成测试中。
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    sync::Arc,
+};
+
+use anyhow::Result;
+use flash_runtime::{
+    core::{AnimationLibrary, AnimationPlayer, BakedPlayer},
+    parser::Animations,
+};
+use serde_json::{Value, json};
+
+/// 统计当前线程的堆分配次数，其它测试线程的分配不计入
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn count_allocation() {
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn placement(time: f32, resource_id: u16) -> Value {
+    json!({
+        "time": time,
+        "resource_id": resource_id,
+        "blend_mode": "Normal",
+        "color_transform": { "mult_color": [1.0, 1.0, 1.0, 1.0], "add_color": [0, 0, 0, 0] },
+        "filters": [],
+    })
+}
+
+fn transform(time: f32) -> Value {
+    json!({ "time": time, "matrix": { "a": 1.0, "b": 0.0, "c": 0.0, "d": 1.0 } })
+}
+
+/// 10帧/秒，根动画10帧：
+/// - 深度1：形状1常驻，作为遮罩遮住深度2
+/// - 深度2：带模糊滤镜的子影片10常驻
+/// - 子影片10共4帧：形状2，第2帧替换为形状3
+fn animations() -> Result<Animations> {
+    let mut mask = placement(0.0, 1);
+    mask["clip_depth"] = json!(2);
+    let mut arm = placement(0.0, 10);
+    arm["filters"] = json!([{ "BlurFilter": { "blur_x": 4.0, "blur_y": 4.0, "flags": 8 } }]);
+    Ok(serde_json::from_value(json!({
+        "meta": { "frame_rate": 10.0, "frames": 10, "version": "test" },
+        "children_clip": {
+            "10": {
+                "name": "arm",
+                "id": 10,
+                "duration": 0.4,
+                "timeline": {
+                    "1": {
+                        "placement": [placement(0.0, 2), placement(0.2, 3)],
+                        "transforms": [transform(0.0), transform(0.2)],
+                    }
+                },
+                "skin_frames": {},
+                "default_skin": "",
+                "events": [{ "time": 0.1, "name": "swing" }],
+            }
+        },
+        "animations": {
+            "default": {
+                "name": "default",
+                "duration": 1.0,
+                "timeline": {
+                    "1": { "placement": [mask], "transforms": [transform(0.0)] },
+                    "2": { "placement": [arm], "transforms": [transform(0.0)] },
+                },
+                "events": [{ "time": 0.5, "name": "hit", "payload": "10" }],
+                "labels": [{ "time": 0.5, "name": "mid" }],
+            }
+        },
+    }))?)
+}
+
+#[test]
+fn steady_state_update_does_not_allocate() -> Result<()> {
+    let library = AnimationLibrary::from(animations()?);
+    let mut player = AnimationPlayer::with_library(Arc::new(library));
+    player.set_play_animation("default", true, None)?;
+    // 同时覆盖子影片和其中形状的颜色
+    player.set_instance_color_transform(
+        "arm",
+        swf::ColorTransform {
+            r_add: 255,
+            ..Default::default()
+        },
+    )?;
+    player.set_instance_color_transform(
+        "arm/1",
+        swf::ColorTransform {
+            g_add: 255,
+            ..Default::default()
+        },
+    )?;
+
+    // 预热一轮循环，之后的更新只复用已有的空间
+    let mut instances = Vec::new();
+    for _ in 0..12 {
+        player.update(&mut instances, 0.1);
+    }
+    let before = ALLOCATIONS.with(Cell::get);
+    for _ in 0..35 {
+        player.update(&mut instances, 0.1);
+    }
+    assert_eq!(ALLOCATIONS.with(Cell::get) - before, 0);
+
+    assert_eq!(instances.len(), 2);
+    assert!(instances[0].is_mask());
+    assert_eq!(instances[1].masked_by(), [instances[0].instance_id()]);
+    assert_eq!(instances[1].filters_mut().len(), 1);
+    let color_transform = instances[1].color_transform();
+    assert_eq!((color_transform.r_add, color_transform.g_add), (255, 255));
+    Ok(())
+}
+
+#[test]
+fn baked_update_does_not_allocate() -> Result<()> {
+    let mut player = BakedPlayer::new(Arc::new(animations()?.bake(10.0)));
+    player.set_play_animation("default", true)?;
+
+    let mut instances = Vec::new();
+    for _ in 0..12 {
+        player.update(&mut instances, 0.1);
+    }
+    let before = ALLOCATIONS.with(Cell::get);
+    for _ in 0..35 {
+        player.update(&mut instances, 0.1);
+    }
+    assert_eq!(ALLOCATIONS.with(Cell::get) - before, 0);
+    assert_eq!(instances.len(), 2);
+    Ok(())
+}